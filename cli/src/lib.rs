@@ -1,14 +1,21 @@
 #![allow(deprecated)]
 use utils::error::Result;
-use clap::{AppSettings, Parser, Subcommand};
+use clap::{AppSettings, Args, Parser, Subcommand};
 use core::commands::{self, Formats};
+use core::csv::{CsvOptions, QuoteStyle, TimestampFormat};
+use core::jsonl::{BinaryEncoding, JsonlOptions};
+use core::dumpin::{DumpInOptions, DEFAULT_BATCH_ROWS};
+use core::parquet::{RowGroupSize, DEFAULT_ROW_GROUP_BYTES, DEFAULT_ROW_GROUP_ROWS};
+use core::dumpout::DumpOutOptions;
+use core::time::{TimeBound, TimeRange};
+use std::path::PathBuf;
 use std::process::exit;
+use utils::taos::{prompt_password, Config, Profile};
 
 #[derive(Parser, Debug)]
 #[clap(name = "taosdump", author, about, long_about = "taosdump CLI", version)]
 #[clap(setting = AppSettings::SubcommandRequired)]
 #[clap(global_setting(AppSettings::DeriveDisplayOrder))]
-
 pub struct Cli {
     /// Action
     #[clap(subcommand)]
@@ -20,11 +27,55 @@ pub struct Cli {
     /// Set target directory path
     pub path: String,
 
-    /// Set dumpout database name
+    /// Set database name to dump out or restore into
     pub name: String,
 
     /// Number of threads
     pub thread: Option<u32>,
+
+    /// Field delimiter of CSV files
+    #[clap(long, default_value_t = ',')]
+    pub delimiter: char,
+
+    /// Fields to quote in CSV files: necessary, always or non-numeric
+    #[clap(long, default_value = "necessary")]
+    pub quote: QuoteStyle,
+
+    /// Timestamps in CSV files: epoch in database precision or rfc3339
+    #[clap(long, default_value = "epoch")]
+    pub timestamp_format: TimestampFormat,
+
+    /// BINARY values in JSON Lines files: escape or base64
+    #[clap(long, default_value = "escape")]
+    pub binary_encoding: BinaryEncoding,
+
+    /// Skip the tables or files finished by an interrupted run
+    #[clap(long)]
+    pub resume: bool,
+
+    /// TDengine server host
+    #[clap(long)]
+    pub host: Option<String>,
+
+    /// TDengine server port
+    #[clap(long, short = 'P')]
+    pub port: Option<u16>,
+
+    /// User to connect as
+    #[clap(long, short)]
+    pub user: Option<String>,
+
+    /// Password of the user, prompted for when given without a value
+    #[clap(long, short, require_equals = true)]
+    pub password: Option<Option<String>>,
+
+    /// TOML file with connection options and named profiles
+    #[clap(long, short)]
+    pub config: Option<PathBuf>,
+
+    /// Connection profile of the config file to use
+    #[clap(long)]
+    pub profile: Option<String>,
 }
 
 #[derive(Subcommand, Debug)]
@@ -34,15 +85,63 @@ enum Commands {
         about = "dumpin to tdengine database",
         long_about = None, 
     )]
-    DumpIn,
+    DumpIn(DumpInArgs),
     #[clap(
         name = "dumpout",
-        about = "dumpout to parquet files",
+        about = "dumpout to data files of the given format",
+        long_about = None, 
+    )]
+    DumpOut(DumpOutArgs),
+    #[clap(
+        name = "verify",
+        about = "verify dumped files against the manifest",
         long_about = None, 
     )]
-    DumpOut,
+    Verify,
+}
+
+#[derive(Args, Debug)]
+struct DumpInArgs {
+    /// Number of rows sent in a single INSERT statement
+    #[clap(long, default_value_t = DEFAULT_BATCH_ROWS)]
+    batch_rows: usize,
+
+    /// Check the restored tables against the row counts and aggregates recorded at dumpout
+    #[clap(long)]
+    validate: bool,
 }
 
+#[derive(Args, Debug)]
+struct DumpOutArgs {
+    /// Maximum number of rows in a Parquet row group
+    #[clap(long, default_value_t = DEFAULT_ROW_GROUP_ROWS, parse(try_from_str = at_least_one))]
+    row_group_rows: usize,
+
+    /// Maximum size in bytes of a Parquet row group before it is flushed
+    #[clap(long, default_value_t = DEFAULT_ROW_GROUP_BYTES, parse(try_from_str = at_least_one))]
+    row_group_bytes: usize,
+
+    /// Dump out rows from this time on, as RFC3339, epoch with unit (s, ms, us, ns) or epoch in database precision
+    #[clap(long)]
+    start_time: Option<TimeBound>,
+
+    /// Dump out rows before this time, in the same formats as --start-time
+    #[clap(long)]
+    end_time: Option<TimeBound>,
+
+    /// Dump out only rows newer than the previous incremental dump, into new files
+    #[clap(long)]
+    incremental: bool,
+}
+
+/// A count or size that must not be zero.
+fn at_least_one(value: &str) -> std::result::Result<usize, String> {
+    match value.parse::<usize>() {
+        Ok(0) => Err("must be at least 1".to_string()),
+        Ok(value) => Ok(value),
+        Err(err) => Err(err.to_string()),
+    }
+}
 
 pub fn cli_match() -> Result<()> {
     // Parse the command line arguments
@@ -51,16 +150,76 @@ pub fn cli_match() -> Result<()> {
     let format = match cli.format.to_lowercase().as_str() {
         "avro" => Formats::Avro,
         "parquet" => Formats::Parquet,
+        "csv" => Formats::Csv,
+        "jsonl" => Formats::Jsonl,
+        "arrow" | "feather" => Formats::Arrow,
+        "sql" => Formats::Sql,
+        "line" | "lp" | "influx" => Formats::LineProtocol,
         _ => {
             eprintln!("unknown format");
             exit(1)
         }
     };
 
+    // Command line options take precedence over the profile, the config file and the environment
+    let config = match &cli.config {
+        Some(path) => Config::load(path)?,
+        None => Config::default(),
+    };
+    let mut connect_options = config.connect_options(cli.profile.as_deref())?;
+    let password = match &cli.password {
+        Some(Some(password)) => Some(password.clone()),
+        Some(None) => Some(prompt_password(cli.user.as_ref().unwrap_or(&connect_options.user))?),
+        None => None,
+    };
+    connect_options.merge(&Profile {
+        host: cli.host.clone(),
+        port: cli.port,
+        user: cli.user.clone(),
+        password,
+    });
+
+    let csv = CsvOptions {
+        delimiter: cli.delimiter,
+        quote: cli.quote,
+        timestamp: cli.timestamp_format,
+    };
+    let jsonl = JsonlOptions {
+        binary: cli.binary_encoding,
+    };
+
     // Execute the subcommand
     match &cli.command {
-        Commands::DumpIn => commands::dumpin(cli.path.as_str(), cli.thread.unwrap_or(1), format)?,
-        Commands::DumpOut => commands::dumpout(cli.path.as_str(), cli.thread.unwrap_or(1), format, cli.name)?,
+        Commands::DumpIn(args) => {
+            let options = DumpInOptions {
+                format,
+                batch_rows: args.batch_rows,
+                csv,
+                jsonl,
+                resume: cli.resume,
+                validate: args.validate,
+            };
+            commands::dumpin(&connect_options, cli.path.as_str(), cli.thread.unwrap_or(1), cli.name, &options)?
+        }
+        Commands::DumpOut(args) => {
+            let options = DumpOutOptions {
+                format,
+                row_group_size: RowGroupSize {
+                    rows: args.row_group_rows,
+                    bytes: args.row_group_bytes,
+                },
+                csv,
+                jsonl,
+                time_range: TimeRange {
+                    start: args.start_time,
+                    end: args.end_time,
+                },
+                incremental: args.incremental,
+                resume: cli.resume,
+            };
+            commands::dumpout(&connect_options, cli.path.as_str(), cli.thread.unwrap_or(1), cli.name, &options)?
+        }
+        Commands::Verify => commands::verify(cli.path.as_str(), format, cli.name, &csv)?,
     }
 
     Ok(())
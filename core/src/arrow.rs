@@ -0,0 +1,463 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::Path;
+use std::sync::Arc;
+
+use arrow::array::{
+    Array, ArrayRef, BinaryArray, BooleanArray, Float32Array, Float64Array, Int16Array, Int32Array,
+    Int64Array, Int8Array, StringArray, TimestampMicrosecondArray, TimestampMillisecondArray,
+    TimestampNanosecondArray, UInt16Array, UInt32Array, UInt64Array, UInt8Array,
+};
+use arrow::datatypes::{DataType, Field as ArrowField, Schema, SchemaRef, TimeUnit};
+use arrow::ipc::reader::FileReader;
+use arrow::ipc::writer::FileWriter;
+use arrow::record_batch::RecordBatch;
+use bstr::BString;
+use libtaos::{Field, Taos, TimestampPrecision};
+use utils::error::{Error, Result};
+
+use crate::dumpin::{
+    create_table_sql, is_nullable, is_string_type, split_length, table_name, InsertBatch,
+};
+use crate::manifest::Summary;
+use crate::time::convert_epoch;
+
+/// Key of the field metadata holding the declared type of a string column, with its length.
+const TYPE_KEY: &str = "tdengine.type";
+
+/// Arrow time unit of timestamps in `precision`.
+fn time_unit(precision: TimestampPrecision) -> TimeUnit {
+    match precision {
+        TimestampPrecision::Micro => TimeUnit::Microsecond,
+        TimestampPrecision::Nano => TimeUnit::Nanosecond,
+        _ => TimeUnit::Millisecond,
+    }
+}
+
+/// Timestamp precision of an Arrow time unit, `None` for seconds, which TDengine has not.
+fn unit_precision(unit: &TimeUnit) -> Option<TimestampPrecision> {
+    match unit {
+        TimeUnit::Millisecond => Some(TimestampPrecision::Milli),
+        TimeUnit::Microsecond => Some(TimestampPrecision::Micro),
+        TimeUnit::Nanosecond => Some(TimestampPrecision::Nano),
+        TimeUnit::Second => None,
+    }
+}
+
+fn generate_arrow_schema(
+    table: &str,
+    column_names: &[String],
+    data_types: &[BString],
+    precision: TimestampPrecision,
+) -> Result<Schema> {
+    let mut fields = vec![];
+    for (index, (name, data_type)) in column_names.iter().zip(data_types).enumerate() {
+        let declared = data_type.to_string();
+        let data_type = match split_length(&declared).0 {
+            "TIMESTAMP" => DataType::Timestamp(time_unit(precision), Some("UTC".to_string())),
+            "BOOL" => DataType::Boolean,
+            "TINYINT" => DataType::Int8,
+            "SMALLINT" => DataType::Int16,
+            "INT" => DataType::Int32,
+            "BIGINT" => DataType::Int64,
+            "TINYINT UNSIGNED" => DataType::UInt8,
+            "SMALLINT UNSIGNED" => DataType::UInt16,
+            "INT UNSIGNED" => DataType::UInt32,
+            "BIGINT UNSIGNED" => DataType::UInt64,
+            "FLOAT" => DataType::Float32,
+            "DOUBLE" => DataType::Float64,
+            // text is UTF-8 for Arrow readers, bytes stay bytes
+            "BINARY" | "VARCHAR" | "NCHAR" | "JSON" => DataType::Utf8,
+            "VARBINARY" | "GEOMETRY" => DataType::Binary,
+            data_type => return Err(Error::unsupported_type(table, name, data_type)),
+        };
+        let field = ArrowField::new(name, data_type, is_nullable(index)).with_metadata(
+            is_string_type(&declared).then(|| BTreeMap::from([(TYPE_KEY.to_string(), declared)])),
+        );
+        fields.push(field);
+    }
+    Ok(Schema::new(fields))
+}
+
+/// Values of column `index`, `None` when one of them does not fit the Arrow type.
+fn column_values<'a, T>(
+    rows: &'a [Vec<Field>],
+    index: usize,
+    value: impl Fn(&'a Field) -> Option<T>,
+) -> Option<Vec<Option<T>>> {
+    rows.iter()
+        .map(|row| match &row[index] {
+            Field::Null => Some(None),
+            field => value(field).map(Some),
+        })
+        .collect()
+}
+
+/// Arrow array of column `index` of `rows`.
+fn column_array(rows: &[Vec<Field>], index: usize, data_type: &DataType) -> Option<ArrayRef> {
+    let array: ArrayRef = match data_type {
+        DataType::Timestamp(unit, tz) => {
+            let values = column_values(rows, index, Field::as_raw_timestamp)?;
+            match unit {
+                TimeUnit::Microsecond => {
+                    Arc::new(TimestampMicrosecondArray::from_opt_vec(values, tz.clone()))
+                }
+                TimeUnit::Nanosecond => {
+                    Arc::new(TimestampNanosecondArray::from_opt_vec(values, tz.clone()))
+                }
+                _ => Arc::new(TimestampMillisecondArray::from_opt_vec(values, tz.clone())),
+            }
+        }
+        DataType::Boolean => Arc::new(BooleanArray::from(column_values(rows, index, |f| {
+            f.as_bool().copied()
+        })?)),
+        DataType::Int8 => Arc::new(Int8Array::from(column_values(rows, index, |f| {
+            f.as_tiny_int().copied()
+        })?)),
+        DataType::Int16 => Arc::new(Int16Array::from(column_values(rows, index, |f| {
+            f.as_small_int().copied()
+        })?)),
+        DataType::Int32 => Arc::new(Int32Array::from(column_values(rows, index, |f| {
+            f.as_int().copied()
+        })?)),
+        DataType::Int64 => Arc::new(Int64Array::from(column_values(rows, index, |f| {
+            f.as_big_int().copied()
+        })?)),
+        DataType::UInt8 => Arc::new(UInt8Array::from(column_values(rows, index, |f| {
+            f.as_unsigned_tiny_int().copied()
+        })?)),
+        DataType::UInt16 => Arc::new(UInt16Array::from(column_values(rows, index, |f| {
+            f.as_unsigned_samll_int().copied()
+        })?)),
+        DataType::UInt32 => Arc::new(UInt32Array::from(column_values(rows, index, |f| {
+            f.as_unsigned_int().copied()
+        })?)),
+        DataType::UInt64 => Arc::new(UInt64Array::from(column_values(rows, index, |f| {
+            f.as_unsigned_big_int().copied()
+        })?)),
+        DataType::Float32 => Arc::new(Float32Array::from(column_values(rows, index, |f| {
+            f.as_float().copied()
+        })?)),
+        DataType::Float64 => Arc::new(Float64Array::from(column_values(rows, index, |f| {
+            f.as_double().copied()
+        })?)),
+        DataType::Binary => Arc::new(BinaryArray::from(column_values(rows, index, |f| {
+            f.as_binary().map(|v| &**v)
+        })?)),
+        DataType::Utf8 => Arc::new(StringArray::from(column_values(
+            rows,
+            index,
+            |f| match f {
+                Field::NChar(v) => Some(v.as_str()),
+                Field::Binary(v) => std::str::from_utf8(v).ok(),
+                _ => None,
+            },
+        )?)),
+        _ => return None,
+    };
+    Some(array)
+}
+
+/// Write the rows of `table` to an Arrow IPC file at `path`, a record batch per batch of rows
+/// fetched, and return the number of values written.
+pub fn arrow_dumpout(
+    path: &Path,
+    table: &str,
+    column_names: &[String],
+    data_types: &[BString],
+    rows: impl Iterator<Item = Result<Vec<Vec<Field>>>>,
+    precision: TimestampPrecision,
+) -> Result<i64> {
+    let schema: SchemaRef = Arc::new(generate_arrow_schema(
+        table,
+        column_names,
+        data_types,
+        precision,
+    )?);
+    let file = File::create(path).map_err(|err| Error::io(path, err))?;
+    let mut writer = FileWriter::try_new(BufWriter::new(file), &schema)
+        .map_err(|err| Error::encode(path, err))?;
+    let mut data_point = 0;
+    for rows in rows {
+        let rows = rows?;
+        let mut columns = vec![];
+        for (index, field) in schema.fields().iter().enumerate() {
+            let array = column_array(&rows, index, field.data_type()).ok_or_else(|| {
+                Error::encode(
+                    path,
+                    format!(
+                        "values of column {} do not fit Arrow type {}",
+                        field.name(),
+                        field.data_type()
+                    ),
+                )
+            })?;
+            columns.push(array);
+        }
+        let batch = RecordBatch::try_new(schema.clone(), columns)
+            .map_err(|err| Error::encode(path, err))?;
+        data_point += (batch.num_rows() * batch.num_columns()) as i64;
+        writer
+            .write(&batch)
+            .map_err(|err| Error::encode(path, err))?;
+    }
+    writer.finish().map_err(|err| Error::encode(path, err))?;
+    Ok(data_point)
+}
+
+/// TDengine type of a column of Arrow type `data_type`.
+fn described_type(data_type: &DataType) -> Option<&'static str> {
+    Some(match data_type {
+        DataType::Timestamp(unit, _) => {
+            unit_precision(unit)?;
+            "TIMESTAMP"
+        }
+        DataType::Boolean => "BOOL",
+        DataType::Int8 => "TINYINT",
+        DataType::Int16 => "SMALLINT",
+        DataType::Int32 => "INT",
+        DataType::Int64 => "BIGINT",
+        DataType::UInt8 => "TINYINT UNSIGNED",
+        DataType::UInt16 => "SMALLINT UNSIGNED",
+        DataType::UInt32 => "INT UNSIGNED",
+        DataType::UInt64 => "BIGINT UNSIGNED",
+        DataType::Float32 => "FLOAT",
+        DataType::Float64 => "DOUBLE",
+        DataType::Binary => "BINARY",
+        DataType::Utf8 => "NCHAR",
+        _ => return None,
+    })
+}
+
+/// Value at `row` of an array, timestamps converted to `precision`.
+fn array_field(array: &ArrayRef, row: usize, precision: TimestampPrecision) -> Option<Field> {
+    if array.is_null(row) {
+        return Some(Field::Null);
+    }
+    let any = array.as_any();
+    Some(match array.data_type() {
+        DataType::Timestamp(unit, _) => {
+            let epoch = match unit {
+                TimeUnit::Microsecond => {
+                    any.downcast_ref::<TimestampMicrosecondArray>()?.value(row)
+                }
+                TimeUnit::Nanosecond => any.downcast_ref::<TimestampNanosecondArray>()?.value(row),
+                _ => any.downcast_ref::<TimestampMillisecondArray>()?.value(row),
+            };
+            Field::BigInt(convert_epoch(epoch, unit_precision(unit)?, precision))
+        }
+        DataType::Boolean => Field::Bool(any.downcast_ref::<BooleanArray>()?.value(row)),
+        DataType::Int8 => Field::TinyInt(any.downcast_ref::<Int8Array>()?.value(row)),
+        DataType::Int16 => Field::SmallInt(any.downcast_ref::<Int16Array>()?.value(row)),
+        DataType::Int32 => Field::Int(any.downcast_ref::<Int32Array>()?.value(row)),
+        DataType::Int64 => Field::BigInt(any.downcast_ref::<Int64Array>()?.value(row)),
+        DataType::UInt8 => Field::UTinyInt(any.downcast_ref::<UInt8Array>()?.value(row)),
+        DataType::UInt16 => Field::USmallInt(any.downcast_ref::<UInt16Array>()?.value(row)),
+        DataType::UInt32 => Field::UInt(any.downcast_ref::<UInt32Array>()?.value(row)),
+        DataType::UInt64 => Field::UBigInt(any.downcast_ref::<UInt64Array>()?.value(row)),
+        DataType::Float32 => Field::Float(any.downcast_ref::<Float32Array>()?.value(row)),
+        DataType::Float64 => Field::Double(any.downcast_ref::<Float64Array>()?.value(row)),
+        DataType::Binary => {
+            Field::Binary(BString::from(any.downcast_ref::<BinaryArray>()?.value(row)))
+        }
+        DataType::Utf8 => Field::NChar(any.downcast_ref::<StringArray>()?.value(row).to_string()),
+        _ => return None,
+    })
+}
+
+fn open_reader(file: &Path) -> Result<FileReader<BufReader<File>>> {
+    let f = File::open(file).map_err(|err| Error::io(file, err))?;
+    FileReader::try_new(BufReader::new(f)).map_err(|err| Error::decode(file, err))
+}
+
+/// Count the rows of an Arrow data file and find the range of its timestamp column, in
+/// `precision`.
+pub fn arrow_summary(file: &Path, precision: TimestampPrecision) -> Result<Summary> {
+    let reader = open_reader(file)?;
+    let mut summary = Summary::default();
+    for batch in reader {
+        let batch = batch.map_err(|err| Error::decode(file, err))?;
+        let ts_column = batch.column(0);
+        for row in 0..batch.num_rows() {
+            let ts = match array_field(ts_column, row, precision) {
+                Some(Field::BigInt(ts)) => Some(ts),
+                _ => None,
+            };
+            summary.add(ts);
+        }
+    }
+    Ok(summary)
+}
+
+pub async fn arrow_dumpin(
+    taos: &Taos,
+    file: &Path,
+    batch_rows: usize,
+    precision: TimestampPrecision,
+) -> Result<()> {
+    let table = table_name(file)?;
+    let reader = open_reader(file)?;
+    let mut columns = vec![];
+    for field in reader.schema().fields() {
+        let declared = field
+            .metadata()
+            .as_ref()
+            .and_then(|metadata| metadata.get(TYPE_KEY));
+        let data_type = match declared {
+            Some(data_type) => data_type.clone(),
+            None => described_type(field.data_type())
+                .ok_or_else(|| Error::unsupported_type(table, field.name(), field.data_type()))?
+                .to_string(),
+        };
+        columns.push((field.name().clone(), data_type));
+    }
+    let sql = create_table_sql(table, &columns)?;
+    taos.query(&sql)
+        .await
+        .map_err(|err| Error::query(&sql, err))?;
+
+    let mut batch = InsertBatch::new(taos, table, file, batch_rows, precision).await?;
+    for record_batch in reader {
+        let record_batch = record_batch.map_err(|err| Error::decode(file, err))?;
+        for row in 0..record_batch.num_rows() {
+            let mut fields = Vec::with_capacity(columns.len());
+            for (index, array) in record_batch.columns().iter().enumerate() {
+                let field = array_field(array, row, precision).ok_or_else(|| {
+                    Error::decode(
+                        file,
+                        format!("invalid value in column {}", columns[index].0),
+                    )
+                })?;
+                fields.push(field);
+            }
+            batch.push(fields).await?;
+        }
+    }
+    batch.flush().await
+}
+
+#[cfg(test)]
+mod tests {
+    use libtaos::Timestamp;
+
+    use super::*;
+
+    #[test]
+    fn rows_round_trip() {
+        let path = std::env::temp_dir().join(format!("round-trip-{}.arrow", std::process::id()));
+        let column_names = ["ts", "b", "v", "n", "vb", "g"].map(String::from);
+        let data_types = [
+            "TIMESTAMP",
+            "BINARY(8)",
+            "VARCHAR(8)",
+            "NCHAR(8)",
+            "VARBINARY(8)",
+            "GEOMETRY(32)",
+        ]
+        .map(BString::from);
+        let rows = vec![
+            vec![
+                Field::Timestamp(Timestamp::new(1, TimestampPrecision::Milli)),
+                Field::Binary("bin".into()),
+                Field::Binary("var".into()),
+                Field::NChar("nç".into()),
+                Field::Binary(vec![0, 0xff].into()),
+                Field::Binary(vec![1, 1, 0, 0, 0].into()),
+            ],
+            vec![
+                Field::Timestamp(Timestamp::new(2, TimestampPrecision::Milli)),
+                Field::Null,
+                Field::Null,
+                Field::Null,
+                Field::Null,
+                Field::Null,
+            ],
+        ];
+        arrow_dumpout(
+            &path,
+            "t",
+            &column_names,
+            &data_types,
+            vec![Ok(rows)].into_iter(),
+            TimestampPrecision::Milli,
+        )
+        .unwrap();
+
+        let reader = open_reader(&path).unwrap();
+        let schema = reader.schema();
+        let types = schema
+            .fields()
+            .iter()
+            .map(|field| field.data_type().clone())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            types[1..],
+            [
+                DataType::Utf8,
+                DataType::Utf8,
+                DataType::Utf8,
+                DataType::Binary,
+                DataType::Binary
+            ]
+        );
+        let declared = schema.fields()[1]
+            .metadata()
+            .as_ref()
+            .and_then(|metadata| metadata.get(TYPE_KEY).cloned());
+        assert_eq!(declared.as_deref(), Some("BINARY(8)"));
+        let mut read = vec![];
+        for batch in reader {
+            let batch = batch.unwrap();
+            for row in 0..batch.num_rows() {
+                let fields = batch
+                    .columns()
+                    .iter()
+                    .map(|array| array_field(array, row, TimestampPrecision::Milli).unwrap())
+                    .collect::<Vec<_>>();
+                read.push(fields);
+            }
+        }
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            read,
+            [
+                vec![
+                    Field::BigInt(1),
+                    Field::NChar("bin".into()),
+                    Field::NChar("var".into()),
+                    Field::NChar("nç".into()),
+                    Field::Binary(vec![0, 0xff].into()),
+                    Field::Binary(vec![1, 1, 0, 0, 0].into()),
+                ],
+                vec![
+                    Field::BigInt(2),
+                    Field::Null,
+                    Field::Null,
+                    Field::Null,
+                    Field::Null,
+                    Field::Null,
+                ],
+            ]
+        );
+    }
+
+    #[test]
+    fn text_must_be_utf8() {
+        let path = std::env::temp_dir().join(format!("not-utf8-{}.arrow", std::process::id()));
+        let rows = vec![vec![
+            Field::Timestamp(Timestamp::new(1, TimestampPrecision::Milli)),
+            Field::Binary(vec![0xff].into()),
+        ]];
+        let result = arrow_dumpout(
+            &path,
+            "t",
+            &["ts", "b"].map(String::from),
+            &["TIMESTAMP", "BINARY(8)"].map(BString::from),
+            vec![Ok(rows)].into_iter(),
+            TimestampPrecision::Milli,
+        );
+        std::fs::remove_file(&path).unwrap();
+        assert!(result.is_err());
+    }
+}
@@ -1,18 +1,80 @@
 use std::{
     fs::File,
-    io::{BufReader, Write},
-    path::PathBuf,
+    io::{BufReader, BufWriter, Write},
+    path::Path,
 };
 
 use avro_rs::{
     types::{Record, Value},
-    Codec, Reader, Schema, Writer,
+    Codec, Decimal, Reader, Schema, Writer,
 };
 use bstr::BString;
-use libtaos::Taos;
+use libtaos::{Field, Taos, TimestampPrecision};
 use serde_json::{self, json, Map};
+use utils::error::{Error, Result};
 
-pub fn generate_avro_schema(column_names: &Vec<String>, data_types: &Vec<BString>) -> Schema {
+use crate::dumpin::{
+    column_sql_type, declared_types, is_nullable, read_declared_types, split_length, table_name,
+    InsertBatch, DEFAULT_STRING_LENGTH,
+};
+use crate::manifest::Summary;
+use crate::time::convert_epoch;
+
+/// Schema of a TIMESTAMP column in `precision`. Avro has no logical type for nanoseconds, so
+/// they are a bare `long`, their precision recorded by the manifest of the dump.
+fn timestamp_schema(precision: TimestampPrecision) -> serde_json::Value {
+    match precision {
+        TimestampPrecision::Micro => json!({ "type": "long", "logicalType": "timestamp-micros" }),
+        TimestampPrecision::Nano => json!("long"),
+        _ => json!({ "type": "long", "logicalType": "timestamp-millis" }),
+    }
+}
+
+/// `v` as an Avro decimal, big-endian two's complement behind a sign byte.
+fn u64_decimal(v: u64) -> Decimal {
+    let mut bytes = vec![0];
+    bytes.extend_from_slice(&v.to_be_bytes());
+    Decimal::from(bytes)
+}
+
+/// Value of an Avro decimal of scale 0, `None` when it is negative or outgrows a `u64`.
+fn decimal_u64(decimal: &Decimal) -> Option<u64> {
+    let bytes = Vec::<u8>::try_from(decimal).ok()?;
+    if bytes.first().is_some_and(|byte| byte & 0x80 != 0) {
+        return None;
+    }
+    let start = bytes.len().saturating_sub(8);
+    if bytes[..start].iter().any(|byte| *byte != 0) {
+        return None;
+    }
+    let mut value = [0; 8];
+    value[8 - (bytes.len() - start)..].copy_from_slice(&bytes[start..]);
+    Some(u64::from_be_bytes(value))
+}
+
+/// Name of the record of `table`, its characters outside of those allowed in Avro names replaced
+/// with `_`.
+fn record_name(table: &str) -> String {
+    let mut name: String = table
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    if !name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+        name.insert(0, '_');
+    }
+    name
+}
+
+/// Schema of the rows of `table`. Avro keeps no attributes of its own on a field, so the
+/// declared types of the columns, with the lengths of the string ones, are the doc of the record.
+/// JSON columns are strings of logical type `json`, which avro-rs leaves out of the file header
+/// as it knows no such type, and GEOMETRY ones the bytes of their WKB.
+pub fn generate_avro_schema(
+    table: &str,
+    column_names: &[String],
+    data_types: &[BString],
+    precision: TimestampPrecision,
+) -> Result<Schema> {
     let mut raw_json_schema = Map::new();
     raw_json_schema.insert(
         "type".to_string(),
@@ -20,119 +82,348 @@ pub fn generate_avro_schema(column_names: &Vec<String>, data_types: &Vec<BString
     );
     raw_json_schema.insert(
         "name".to_string(),
-        serde_json::Value::String("m1".to_string()),
+        serde_json::Value::String(record_name(table)),
     );
     let mut field_json_array: Vec<serde_json::Value> = vec![];
     for i in 0..column_names.len() {
-        let column = json!({ "name": column_names[i], "type": match data_types[i].to_string().as_str() {
-            "TINYINT" | "TINYINT UNSIGNED" | "SMALLINT" | "SMALLINT UNSIGNED" | "INT" => "int",
-            "INT UNSIGNED" | "BIGINT" | "TIMESTAMP" | "BIGINT UNSIGNED" => "long",
-            "BOOL" => "boolean",
-            "FLOAT" => "float",
-            "DOUBLE" => "double",
-            "BINARY" => "bytes",
-            "NCHAR" => "string",
-            _ => unreachable!("unexpected data type, please contact the author to fix!"),
-        }});
+        let data_type = match split_length(&data_types[i].to_string()).0 {
+            "TINYINT" | "TINYINT UNSIGNED" | "SMALLINT" | "SMALLINT UNSIGNED" | "INT" => {
+                json!("int")
+            }
+            "INT UNSIGNED" | "BIGINT" => json!("long"),
+            // Avro has no unsigned types and a long stops at i64::MAX
+            "BIGINT UNSIGNED" => {
+                json!({ "type": "bytes", "logicalType": "decimal", "precision": 20, "scale": 0 })
+            }
+            "TIMESTAMP" => timestamp_schema(precision),
+            "BOOL" => json!("boolean"),
+            "FLOAT" => json!("float"),
+            "DOUBLE" => json!("double"),
+            "BINARY" | "VARCHAR" | "VARBINARY" | "GEOMETRY" => json!("bytes"),
+            "JSON" => json!({ "type": "string", "logicalType": "json" }),
+            "NCHAR" => json!("string"),
+            data_type => return Err(Error::unsupported_type(table, &column_names[i], data_type)),
+        };
+        let column = if is_nullable(i) {
+            json!({ "name": column_names[i], "type": ["null", data_type] })
+        } else {
+            json!({ "name": column_names[i], "type": data_type })
+        };
         field_json_array.push(column);
     }
+    raw_json_schema.insert(
+        "doc".to_string(),
+        serde_json::Value::String(declared_types(column_names, data_types)),
+    );
     raw_json_schema.insert(
         "fields".to_string(),
         serde_json::Value::Array(field_json_array),
     );
 
-    println!("{:?}", serde_json::to_string(&raw_json_schema).unwrap());
-
-    Schema::parse_str(serde_json::to_string(&raw_json_schema).unwrap().as_str()).unwrap()
+    Schema::parse_str(&serde_json::Value::Object(raw_json_schema).to_string())
+        .map_err(|err| Error::schema(table, err.to_string()))
 }
 
+/// Write the rows of `table` to an Avro file at `path` and return the number of values written.
 pub fn avro_dumpout(
-    mut file: File,
-    column_names: &Vec<String>,
-    data_types: &Vec<BString>,
-    rows: Vec<Vec<libtaos::Field>>,
-) -> i64 {
-    let schema = generate_avro_schema(column_names, data_types);
-    let mut writer = Writer::with_codec(&schema, Vec::new(), Codec::Deflate);
+    path: &Path,
+    table: &str,
+    column_names: &[String],
+    data_types: &[BString],
+    rows: impl Iterator<Item = Result<Vec<Vec<libtaos::Field>>>>,
+    precision: TimestampPrecision,
+) -> Result<i64> {
+    let schema = generate_avro_schema(table, column_names, data_types, precision)?;
+    let file = File::create(path).map_err(|err| Error::io(path, err))?;
+    let mut writer = Writer::with_codec(&schema, BufWriter::new(file), Codec::Deflate);
+    let is_json = data_types
+        .iter()
+        .map(|data_type| split_length(&data_type.to_string()).0 == "JSON")
+        .collect::<Vec<_>>();
     let mut data_point = 0;
-    for row in rows {
-        let mut record = Record::new(writer.schema()).unwrap();
-        let mut index = 0;
-        for field in row {
-            match field {
-                libtaos::Field::Null => todo!(),
-                libtaos::Field::Bool(v) => record.put(column_names[index].as_str(), v),
-                libtaos::Field::TinyInt(v) => record.put(column_names[index].as_str(), v as i32),
-                libtaos::Field::SmallInt(v) => record.put(column_names[index].as_str(), v as i32),
-                libtaos::Field::Int(v) => record.put(column_names[index].as_str(), v),
-                libtaos::Field::BigInt(v) => record.put(column_names[index].as_str(), v),
-                libtaos::Field::Float(v) => record.put(column_names[index].as_str(), v),
-                libtaos::Field::Double(v) => record.put(column_names[index].as_str(), v),
-                libtaos::Field::Binary(v) => {
-                    record.put(column_names[index].as_str(), (&*v).to_vec())
-                }
-                libtaos::Field::Timestamp(v) => {
-                    record.put(column_names[index].as_str(), v.as_raw_timestamp())
+    for rows in rows {
+        for row in rows? {
+            let mut record = Record::new(writer.schema())
+                .ok_or_else(|| Error::schema(table, "avro schema is not a record"))?;
+            for (index, field) in row.into_iter().enumerate() {
+                let value = match field {
+                    libtaos::Field::Null => Value::Null,
+                    libtaos::Field::Bool(v) => Value::Boolean(v),
+                    libtaos::Field::TinyInt(v) => Value::Int(v as i32),
+                    libtaos::Field::SmallInt(v) => Value::Int(v as i32),
+                    libtaos::Field::Int(v) => Value::Int(v),
+                    libtaos::Field::BigInt(v) => Value::Long(v),
+                    libtaos::Field::Float(v) => Value::Float(v),
+                    libtaos::Field::Double(v) => Value::Double(v),
+                    libtaos::Field::Binary(v) if is_json[index] => {
+                        match String::from_utf8(v.to_vec()) {
+                            Ok(v) => Value::String(v),
+                            Err(err) => return Err(Error::encode(path, err)),
+                        }
+                    }
+                    libtaos::Field::Binary(v) => Value::Bytes(v.to_vec()),
+                    libtaos::Field::Timestamp(v) => Value::Long(v.as_raw_timestamp()),
+                    libtaos::Field::NChar(v) => Value::String(v),
+                    libtaos::Field::UTinyInt(v) => Value::Int(v as i32),
+                    libtaos::Field::USmallInt(v) => Value::Int(v as i32),
+                    libtaos::Field::UInt(v) => Value::Long(v as i64),
+                    libtaos::Field::UBigInt(v) => Value::Decimal(u64_decimal(v)),
+                };
+                if index == 0 {
+                    record.put(column_names[index].as_str(), value);
+                } else {
+                    record.put(column_names[index].as_str(), Value::Union(Box::new(value)));
                 }
-                libtaos::Field::NChar(v) => record.put(column_names[index].as_str(), v),
-                libtaos::Field::UTinyInt(v) => record.put(column_names[index].as_str(), v as i32),
-                libtaos::Field::USmallInt(v) => record.put(column_names[index].as_str(), v as i32),
-                libtaos::Field::UInt(v) => record.put(column_names[index].as_str(), v as i64),
-                libtaos::Field::UBigInt(v) => record.put(column_names[index].as_str(), v as i64),
-            };
-            index += 1;
-            data_point += 1;
+                data_point += 1;
+            }
+            writer
+                .append(record)
+                .map_err(|err| Error::encode(path, err))?;
         }
-        writer.append(record).unwrap();
     }
-    let input = writer.into_inner().unwrap();
-    file.write(&input).unwrap();
-    data_point
+    writer
+        .into_inner()
+        .map_err(|err| Error::encode(path, err))?
+        .flush()
+        .map_err(|err| Error::io(path, err))?;
+    Ok(data_point)
 }
 
-#[tokio::main]
-pub async fn avro_dumpin(file_list: &Vec<PathBuf>, taos: Taos) {
-    assert_eq!(
-        taos.query("create database if not exists demo")
-            .await
-            .is_ok(),
-        true
-    );
-    assert_eq!(taos.query("use demo").await.is_ok(), true);
-    assert_eq!(taos.query("create table m1 (ts timestamp,c1 tinyint,c2 tinyint unsigned,c3 smallint,c4 smallint unsigned,c5 int,c6 int unsigned,c7 bigint,c8 bigint unsigned,c9 float,c10 double,c11 binary(8),c12 nchar(8),c13 bool)").await.is_ok(), true);
-
-    for file in file_list {
-        let f = File::open(file).unwrap();
-        let buffered_reader = BufReader::new(f);
-        let r = Reader::new(buffered_reader);
-        let mut sql = "insert into m1 values".to_string();
-        for x in r.unwrap() {
-            let mut _count = 0;
-            sql += "(";
-            match x.unwrap() {
-                Value::Record(r) => {
-                    for row in r.into_iter() {
-                        if _count != 0 {
-                            sql += ",";
+/// Strip the `["null", T]` union wrapping a nullable column.
+fn non_null_schema(schema: &Schema) -> &Schema {
+    match schema {
+        Schema::Union(union) => union
+            .variants()
+            .iter()
+            .find(|variant| !matches!(variant, Schema::Null))
+            .unwrap_or(schema),
+        _ => schema,
+    }
+}
+
+fn avro_create_table_sql(table: &str, schema: &Schema) -> Result<String> {
+    let (fields, doc) = match schema {
+        Schema::Record { fields, doc, .. } => (fields, doc),
+        _ => return Err(Error::schema(table, "avro schema is not a record")),
+    };
+    let declared = read_declared_types(doc.as_deref());
+    let mut sql = format!("create table if not exists {} (", table);
+    for (index, field) in fields.iter().enumerate() {
+        if index != 0 {
+            sql += ",";
+        }
+        sql += &field.name;
+        if let Some(data_type) = declared.get(&field.name) {
+            sql += " ";
+            sql += &column_sql_type(table, &field.name, data_type)?;
+            continue;
+        }
+        match non_null_schema(&field.schema) {
+            Schema::Boolean => sql += " bool",
+            Schema::Int => sql += " int",
+            Schema::TimestampMillis | Schema::TimestampMicros => sql += " timestamp",
+            // the first column of a TDengine table is always the timestamp
+            Schema::Long if index == 0 => sql += " timestamp",
+            Schema::Long => sql += " bigint",
+            Schema::Float => sql += " float",
+            Schema::Double => sql += " double",
+            Schema::Decimal {
+                precision: 20,
+                scale: 0,
+                ..
+            } => sql += " bigint unsigned",
+            Schema::Bytes => sql += &format!(" binary({})", DEFAULT_STRING_LENGTH),
+            Schema::String => sql += &format!(" nchar({})", DEFAULT_STRING_LENGTH),
+            schema => {
+                return Err(Error::unsupported_type(
+                    table,
+                    &field.name,
+                    format!("{:?}", schema),
+                ))
+            }
+        }
+    }
+    sql += ")";
+    Ok(sql)
+}
+
+/// Count the rows of an Avro data file and find the range of its timestamp column.
+pub fn avro_summary(file: &Path) -> Result<Summary> {
+    let f = File::open(file).map_err(|err| Error::io(file, err))?;
+    let r = Reader::new(BufReader::new(f)).map_err(|err| Error::decode(file, err))?;
+    let mut summary = Summary::default();
+    for x in r {
+        let ts = match x.map_err(|err| Error::decode(file, err))? {
+            Value::Record(fields) => match fields.into_iter().next() {
+                Some((_, Value::Long(v)))
+                | Some((_, Value::TimestampMillis(v)))
+                | Some((_, Value::TimestampMicros(v))) => Some(v),
+                _ => None,
+            },
+            _ => return Err(Error::decode(file, "avro value is not a record")),
+        };
+        summary.add(ts);
+    }
+    Ok(summary)
+}
+
+/// Load an Avro data file into a database of `precision`, converting timestamps from their
+/// logical type, or from `source`, the precision of the dumped database, for a bare `long`.
+pub async fn avro_dumpin(
+    taos: &Taos,
+    file: &Path,
+    batch_rows: usize,
+    source: TimestampPrecision,
+    precision: TimestampPrecision,
+) -> Result<()> {
+    let table = table_name(file)?;
+    let f = File::open(file).map_err(|err| Error::io(file, err))?;
+    let buffered_reader = BufReader::new(f);
+    let r = Reader::new(buffered_reader).map_err(|err| Error::decode(file, err))?;
+    let sql = avro_create_table_sql(table, r.writer_schema())?;
+    taos.query(&sql)
+        .await
+        .map_err(|err| Error::query(&sql, err))?;
+    let mut batch = InsertBatch::new(taos, table, file, batch_rows, precision).await?;
+    for x in r {
+        let mut fields = vec![];
+        match x.map_err(|err| Error::decode(file, err))? {
+            Value::Record(r) => {
+                for (index, (name, value)) in r.into_iter().enumerate() {
+                    let value = match value {
+                        Value::Union(v) => *v,
+                        v => v,
+                    };
+                    let field = match value {
+                        Value::Null => Field::Null,
+                        Value::Boolean(v) => Field::Bool(v),
+                        Value::Int(v) => Field::Int(v),
+                        Value::Long(v) if index == 0 => {
+                            Field::BigInt(convert_epoch(v, source, precision))
                         }
-                        _count += 1;
-                        match row.1 {
-                            Value::Null => todo!(),
-                            Value::Boolean(v) => sql += &v.to_string(),
-                            Value::Int(v) => sql += &v.to_string(),
-                            Value::Long(v) => sql += &v.to_string(),
-                            Value::Float(v) => sql += &v.to_string(),
-                            Value::Double(v) => sql += &v.to_string(),
-                            Value::Bytes(v) => sql += String::from_utf8(v).unwrap().as_str(),
-                            Value::String(v) => sql += &v.to_string(),
-                            _ => unreachable!(),
+                        Value::Long(v) => Field::BigInt(v),
+                        Value::TimestampMillis(v) => {
+                            Field::BigInt(convert_epoch(v, TimestampPrecision::Milli, precision))
                         }
-                    }
+                        Value::TimestampMicros(v) => {
+                            Field::BigInt(convert_epoch(v, TimestampPrecision::Micro, precision))
+                        }
+                        Value::Float(v) => Field::Float(v),
+                        Value::Double(v) => Field::Double(v),
+                        Value::Bytes(v) => Field::Binary(BString::from(v)),
+                        Value::Decimal(v) => match decimal_u64(&v) {
+                            Some(v) => Field::UBigInt(v),
+                            None => {
+                                return Err(Error::decode(
+                                    file,
+                                    format!("decimal of column {} is not an unsigned bigint", name),
+                                ))
+                            }
+                        },
+                        Value::String(v) => Field::NChar(v),
+                        value => {
+                            return Err(Error::unsupported_type(
+                                table,
+                                name,
+                                format!("{:?}", value),
+                            ))
+                        }
+                    };
+                    fields.push(field);
                 }
-                _ => unreachable!(),
             }
-            sql += ")";
+            _ => return Err(Error::decode(file, "avro value is not a record")),
+        }
+        batch.push(fields).await?;
+    }
+    batch.flush().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn u64_round_trips_through_decimal() {
+        for v in [
+            0,
+            1,
+            255,
+            256,
+            i64::MAX as u64,
+            i64::MAX as u64 + 1,
+            u64::MAX,
+        ] {
+            assert_eq!(decimal_u64(&u64_decimal(v)), Some(v), "{}", v);
+        }
+    }
+
+    #[test]
+    fn decimal_out_of_u64() {
+        // negative, and one more than u64::MAX
+        assert_eq!(decimal_u64(&Decimal::from(vec![0xff])), None);
+        assert_eq!(
+            decimal_u64(&Decimal::from(vec![1, 0, 0, 0, 0, 0, 0, 0, 0])),
+            None
+        );
+        assert_eq!(decimal_u64(&Decimal::from(vec![0, 0, 0, 1])), Some(1));
+    }
+
+    #[test]
+    fn declared_types_survive_the_file() {
+        let column_names = ["ts", "a", "b", "c", "d", "e"].map(String::from);
+        let data_types = [
+            "TIMESTAMP",
+            "TINYINT",
+            "SMALLINT",
+            "INT UNSIGNED",
+            "BIGINT UNSIGNED",
+            "VARBINARY(16)",
+        ]
+        .map(BString::from);
+        let schema =
+            generate_avro_schema("t", &column_names, &data_types, TimestampPrecision::Milli)
+                .unwrap();
+        // as the schema is written to the header of the file and read back
+        let schema = Schema::parse_str(&serde_json::to_string(&schema).unwrap()).unwrap();
+        assert_eq!(
+            avro_create_table_sql("t", &schema).unwrap(),
+            "create table if not exists t (ts timestamp,a tinyint,b smallint,c int unsigned,\
+             d bigint unsigned,e varbinary(16))"
+        );
+    }
+
+    #[test]
+    fn json_is_string_and_geometry_bytes() {
+        let column_names = ["ts", "j", "g"].map(String::from);
+        let data_types = ["TIMESTAMP", "JSON", "GEOMETRY(64)"].map(BString::from);
+        let schema =
+            generate_avro_schema("t", &column_names, &data_types, TimestampPrecision::Milli)
+                .unwrap();
+        let fields = match &schema {
+            Schema::Record { fields, .. } => fields,
+            _ => panic!("not a record"),
+        };
+        assert!(matches!(non_null_schema(&fields[1].schema), Schema::String));
+        assert!(matches!(non_null_schema(&fields[2].schema), Schema::Bytes));
+        assert_eq!(
+            avro_create_table_sql("t", &schema).unwrap(),
+            "create table if not exists t (ts timestamp,j json,g geometry(64))"
+        );
+    }
+
+    #[test]
+    fn record_is_named_after_table() {
+        let column_names = ["ts"].map(String::from);
+        let data_types = ["TIMESTAMP"].map(BString::from);
+        for (table, expected) in [("d1001", "d1001"), ("1-a", "_1_a")] {
+            let schema =
+                generate_avro_schema(table, &column_names, &data_types, TimestampPrecision::Milli)
+                    .unwrap();
+            match schema {
+                Schema::Record { name, .. } => assert_eq!(name.name, expected),
+                _ => panic!("not a record"),
+            }
         }
-        assert_eq!(taos.query(sql.as_str()).await.is_ok(), true);
     }
 }
@@ -1,21 +1,37 @@
-use std::path::PathBuf;
-
-use super::dumpin;
-use super::dumpout;
+use super::csv::CsvOptions;
+use super::dumpin::{self, DumpInOptions};
+use super::dumpout::{self, DumpOutOptions};
+use super::manifest;
 use utils::error::Result;
 use utils::get_all_data_files;
+use utils::taos::ConnectOptions;
 pub use utils::Formats;
 
-pub fn dumpin(path: &str, thread: u32, format: Formats) -> Result<()> {
-    let mut file_list: Vec<PathBuf> = vec![];
-    get_all_data_files(path, &mut file_list, &format);
-    dumpin::start(&file_list, thread, format)?;
+pub fn dumpin(
+    connect_options: &ConnectOptions,
+    path: &str,
+    thread: u32,
+    name: String,
+    options: &DumpInOptions,
+) -> Result<()> {
+    let file_list = get_all_data_files(path, &options.format)?;
+    dumpin::start(connect_options, path, &file_list, thread, name, options)?;
     Ok(())
 }
 
-pub fn dumpout(path: &str, thread: u32, format: Formats, name: String) -> Result<()> {
-    dumpout::dumpout_database_sql(path, name.clone())?;
-    dumpout::dumpout_stable_sql(path, name.clone())?;
-    dumpout::start(path, thread, format)?;
+pub fn verify(path: &str, format: Formats, name: String, csv: &CsvOptions) -> Result<()> {
+    manifest::verify(path, &name, &format, csv)
+}
+
+pub fn dumpout(
+    connect_options: &ConnectOptions,
+    path: &str,
+    thread: u32,
+    name: String,
+    options: &DumpOutOptions,
+) -> Result<()> {
+    dumpout::dumpout_database_sql(connect_options, path, name.clone())?;
+    let stables = dumpout::dumpout_stable_sql(connect_options, path, name.clone())?;
+    dumpout::start(connect_options, path, thread, name, &stables, options)?;
     Ok(())
 }
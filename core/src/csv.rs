@@ -0,0 +1,585 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::mem;
+use std::path::Path;
+use std::str::FromStr;
+
+use bstr::BString;
+use libtaos::{Field, Taos, TimestampPrecision};
+use utils::error::{Error, Result};
+
+use crate::dumpin::{
+    column_sql_type, create_table_sql, is_bytes_type, split_length, table_name, InsertBatch,
+};
+use crate::dumpout::hex;
+use crate::manifest::Summary;
+use crate::time::{to_rfc3339, TimeBound};
+
+/// Which fields of a CSV data file are enclosed in double quotes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuoteStyle {
+    /// Only fields holding the delimiter, a double quote or a line break, and empty strings.
+    Necessary,
+    /// Every field but NULL.
+    Always,
+    /// Every field that is not a number, NULL excepted.
+    NonNumeric,
+}
+
+impl FromStr for QuoteStyle {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "necessary" => Ok(QuoteStyle::Necessary),
+            "always" => Ok(QuoteStyle::Always),
+            "non-numeric" => Ok(QuoteStyle::NonNumeric),
+            _ => Err(format!(
+                "{} is not one of necessary, always, non-numeric",
+                s
+            )),
+        }
+    }
+}
+
+/// How timestamps are written to CSV data files. Both are read back by dumpin.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimestampFormat {
+    /// Epoch in the precision of the database.
+    Epoch,
+    /// RFC3339 time in UTC, such as `2022-03-01T00:00:00.000Z`.
+    Rfc3339,
+}
+
+impl FromStr for TimestampFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "epoch" => Ok(TimestampFormat::Epoch),
+            "rfc3339" => Ok(TimestampFormat::Rfc3339),
+            _ => Err(format!("{} is not one of epoch, rfc3339", s)),
+        }
+    }
+}
+
+/// Layout of CSV data files.
+///
+/// The header row names each column along with its TDengine type, as in `ts:TIMESTAMP` or
+/// `name:BINARY(20)`, so that dumpin can create the table. An empty field is NULL, while an
+/// empty string is always quoted.
+#[derive(Debug, Clone, Copy)]
+pub struct CsvOptions {
+    pub delimiter: char,
+    pub quote: QuoteStyle,
+    pub timestamp: TimestampFormat,
+}
+
+impl Default for CsvOptions {
+    fn default() -> Self {
+        CsvOptions {
+            delimiter: ',',
+            quote: QuoteStyle::Necessary,
+            timestamp: TimestampFormat::Epoch,
+        }
+    }
+}
+
+/// Text of a field of a column of type `data_type`, and whether it is a number, `None` for
+/// NULL. VARBINARY values and the WKB of GEOMETRY ones are written in hex, as in `\x0aff`, and
+/// other bytes must be UTF-8.
+fn render_field(
+    path: &Path,
+    field: &Field,
+    data_type: &str,
+    options: &CsvOptions,
+    precision: TimestampPrecision,
+) -> Result<Option<(String, bool)>> {
+    Ok(Some(match field {
+        Field::Null => return Ok(None),
+        Field::Bool(v) => (v.to_string(), false),
+        Field::Binary(v) if is_bytes_type(data_type) => (hex(v), false),
+        Field::Binary(v) => match std::str::from_utf8(v) {
+            Ok(v) => (v.to_string(), false),
+            Err(err) => return Err(Error::encode(path, err)),
+        },
+        Field::NChar(v) => (v.clone(), false),
+        Field::Timestamp(v) => match options.timestamp {
+            TimestampFormat::Epoch => (v.as_raw_timestamp().to_string(), true),
+            TimestampFormat::Rfc3339 => (to_rfc3339(v, precision), false),
+        },
+        field => (field.to_string(), true),
+    }))
+}
+
+/// Bytes written by [`hex`], `None` when `value` is not.
+fn unhex(value: &str) -> Option<Vec<u8>> {
+    let digits = value.strip_prefix("\\x")?.as_bytes();
+    if digits.len() % 2 != 0 || !digits.iter().all(u8::is_ascii_hexdigit) {
+        return None;
+    }
+    digits
+        .chunks(2)
+        .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok())
+        .collect()
+}
+
+/// Append a field to a line of the file, quoted as `options` asks.
+fn push_field(line: &mut String, value: &str, is_number: bool, options: &CsvOptions) {
+    let quote = match options.quote {
+        QuoteStyle::Always => true,
+        QuoteStyle::NonNumeric if !is_number => true,
+        _ => value.is_empty() || value.contains([options.delimiter, '"', '\n', '\r']),
+    };
+    if quote {
+        line.push('"');
+        line.push_str(&value.replace('"', "\"\""));
+        line.push('"');
+    } else {
+        line.push_str(value);
+    }
+}
+
+/// Write the rows of `table` to a CSV file at `path` and return the number of values written.
+pub fn csv_dumpout(
+    path: &Path,
+    table: &str,
+    column_names: &[String],
+    data_types: &[BString],
+    rows: impl Iterator<Item = Result<Vec<Vec<Field>>>>,
+    options: &CsvOptions,
+    precision: TimestampPrecision,
+) -> Result<i64> {
+    let mut line = String::new();
+    for (index, (name, data_type)) in column_names.iter().zip(data_types).enumerate() {
+        let data_type = data_type.to_string();
+        column_sql_type(table, name, &data_type)?;
+        if index != 0 {
+            line.push(options.delimiter);
+        }
+        push_field(
+            &mut line,
+            &format!("{}:{}", name, data_type),
+            false,
+            options,
+        );
+    }
+    line.push('\n');
+
+    let file = File::create(path).map_err(|err| Error::io(path, err))?;
+    let mut writer = BufWriter::new(file);
+    writer
+        .write_all(line.as_bytes())
+        .map_err(|err| Error::io(path, err))?;
+    let data_types = data_types
+        .iter()
+        .map(|data_type| data_type.to_string())
+        .collect::<Vec<_>>();
+    let mut data_point = 0;
+    for rows in rows {
+        for row in rows? {
+            line.clear();
+            for (index, (field, data_type)) in row.iter().zip(&data_types).enumerate() {
+                if index != 0 {
+                    line.push(options.delimiter);
+                }
+                if let Some((value, is_number)) =
+                    render_field(path, field, data_type, options, precision)?
+                {
+                    push_field(&mut line, &value, is_number, options);
+                }
+                data_point += 1;
+            }
+            line.push('\n');
+            writer
+                .write_all(line.as_bytes())
+                .map_err(|err| Error::io(path, err))?;
+        }
+    }
+    writer.flush().map_err(|err| Error::io(path, err))?;
+    Ok(data_point)
+}
+
+/// Reads the records of a CSV file, a field being `None` when it is NULL.
+struct CsvReader<R> {
+    reader: R,
+    delimiter: char,
+    line: String,
+}
+
+impl<R: BufRead> CsvReader<R> {
+    fn new(reader: R, delimiter: char) -> Self {
+        CsvReader {
+            reader,
+            delimiter,
+            line: String::new(),
+        }
+    }
+
+    fn read_record(&mut self) -> io::Result<Option<Vec<Option<String>>>> {
+        // blank lines hold no record
+        loop {
+            self.line.clear();
+            if self.reader.read_line(&mut self.line)? == 0 {
+                return Ok(None);
+            }
+            if !self.line.trim_end_matches(['\r', '\n']).is_empty() {
+                break;
+            }
+        }
+
+        let mut record = vec![];
+        let mut field = String::new();
+        let mut quoted = false;
+        let mut in_quotes = false;
+        loop {
+            let mut chars = self.line.chars().peekable();
+            while let Some(c) = chars.next() {
+                if in_quotes {
+                    if c != '"' {
+                        field.push(c);
+                    } else if chars.peek() == Some(&'"') {
+                        chars.next();
+                        field.push('"');
+                    } else {
+                        in_quotes = false;
+                    }
+                } else if c == '"' && field.is_empty() && !quoted {
+                    quoted = true;
+                    in_quotes = true;
+                } else if c == self.delimiter {
+                    record.push(take_field(&mut field, &mut quoted));
+                } else if c != '\n' && c != '\r' {
+                    field.push(c);
+                }
+            }
+            // a quoted field goes on with the line break and the next line
+            if !in_quotes {
+                break;
+            }
+            self.line.clear();
+            if self.reader.read_line(&mut self.line)? == 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "quoted field is not terminated",
+                ));
+            }
+        }
+        record.push(take_field(&mut field, &mut quoted));
+        Ok(Some(record))
+    }
+}
+
+impl<R: BufRead> Iterator for CsvReader<R> {
+    type Item = io::Result<Vec<Option<String>>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_record().transpose()
+    }
+}
+
+/// An unquoted empty field is NULL.
+fn take_field(field: &mut String, quoted: &mut bool) -> Option<String> {
+    let value = match field.is_empty() && !*quoted {
+        true => None,
+        false => Some(mem::take(field)),
+    };
+    *quoted = false;
+    value
+}
+
+/// Names and types of the columns listed by the header row of `file`.
+fn read_header(
+    file: &Path,
+    table: &str,
+    reader: &mut CsvReader<impl BufRead>,
+) -> Result<Vec<(String, String)>> {
+    let header = match reader.next() {
+        Some(header) => header.map_err(|err| Error::io(file, err))?,
+        None => return Err(Error::schema(table, "CSV file has no header row")),
+    };
+    header
+        .into_iter()
+        .map(|column| {
+            column
+                .as_deref()
+                .and_then(|column| column.split_once(':'))
+                .map(|(name, data_type)| (name.to_string(), data_type.to_string()))
+                .ok_or_else(|| {
+                    Error::schema(
+                        table,
+                        format!("CSV header {:?} is not <name>:<type>", column),
+                    )
+                })
+        })
+        .collect()
+}
+
+/// Value of a field as dumped out of a column of type `data_type`, `None` when it is invalid,
+/// with timestamps converted from `source` to `precision`.
+fn parse_field(
+    value: &str,
+    data_type: &str,
+    source: TimestampPrecision,
+    precision: TimestampPrecision,
+) -> Option<Field> {
+    Some(match split_length(data_type).0 {
+        "TIMESTAMP" => Field::BigInt(
+            value
+                .parse::<TimeBound>()
+                .ok()?
+                .to_epoch_from(source, precision),
+        ),
+        "BOOL" => Field::Bool(value.parse().ok()?),
+        "TINYINT" => Field::TinyInt(value.parse().ok()?),
+        "SMALLINT" => Field::SmallInt(value.parse().ok()?),
+        "INT" => Field::Int(value.parse().ok()?),
+        "BIGINT" => Field::BigInt(value.parse().ok()?),
+        "FLOAT" => Field::Float(value.parse().ok()?),
+        "DOUBLE" => Field::Double(value.parse().ok()?),
+        "TINYINT UNSIGNED" => Field::UTinyInt(value.parse().ok()?),
+        "SMALLINT UNSIGNED" => Field::USmallInt(value.parse().ok()?),
+        "INT UNSIGNED" => Field::UInt(value.parse().ok()?),
+        "BIGINT UNSIGNED" => Field::UBigInt(value.parse().ok()?),
+        "BINARY" | "VARCHAR" | "JSON" => Field::Binary(BString::from(value)),
+        "VARBINARY" | "GEOMETRY" => Field::Binary(BString::from(unhex(value)?)),
+        "NCHAR" => Field::NChar(value.to_string()),
+        _ => return None,
+    })
+}
+
+/// Count the rows of a CSV data file and find the range of its timestamp column, read in
+/// `precision` when written as RFC3339.
+pub fn csv_summary(
+    file: &Path,
+    options: &CsvOptions,
+    precision: TimestampPrecision,
+) -> Result<Summary> {
+    let f = File::open(file).map_err(|err| Error::io(file, err))?;
+    let mut reader = CsvReader::new(BufReader::new(f), options.delimiter);
+    let table = table_name(file)?;
+    read_header(file, table, &mut reader)?;
+    let mut summary = Summary::default();
+    for record in reader {
+        let record = record.map_err(|err| Error::io(file, err))?;
+        let ts = record
+            .first()
+            .and_then(|value| value.as_deref())
+            .and_then(|value| value.parse::<TimeBound>().ok())
+            .map(|ts| ts.to_epoch(precision));
+        summary.add(ts);
+    }
+    Ok(summary)
+}
+
+/// Load a CSV data file into a database of `precision`, where epochs are in `source`, the
+/// precision of the dumped database.
+pub async fn csv_dumpin(
+    taos: &Taos,
+    file: &Path,
+    batch_rows: usize,
+    options: &CsvOptions,
+    source: TimestampPrecision,
+    precision: TimestampPrecision,
+) -> Result<()> {
+    let table = table_name(file)?;
+    let f = File::open(file).map_err(|err| Error::io(file, err))?;
+    let mut reader = CsvReader::new(BufReader::new(f), options.delimiter);
+    let columns = read_header(file, table, &mut reader)?;
+    let sql = create_table_sql(table, &columns)?;
+    taos.query(&sql)
+        .await
+        .map_err(|err| Error::query(&sql, err))?;
+
+    let mut batch = InsertBatch::new(taos, table, file, batch_rows, precision).await?;
+    for (line, record) in reader.enumerate() {
+        let record = record.map_err(|err| Error::io(file, err))?;
+        if record.len() != columns.len() {
+            return Err(Error::decode(
+                file,
+                format!(
+                    "record {} has {} fields, expected {}",
+                    line + 1,
+                    record.len(),
+                    columns.len()
+                ),
+            ));
+        }
+        let mut row = Vec::with_capacity(columns.len());
+        for (value, (name, data_type)) in record.iter().zip(&columns) {
+            let field = match value {
+                Some(value) => {
+                    parse_field(value, data_type, source, precision).ok_or_else(|| {
+                        Error::decode(
+                            file,
+                            format!(
+                                "invalid {} value {:?} of column {} in record {}",
+                                data_type,
+                                value,
+                                name,
+                                line + 1
+                            ),
+                        )
+                    })?
+                }
+                None => Field::Null,
+            };
+            row.push(field);
+        }
+        batch.push(row).await?;
+    }
+    batch.flush().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn records(content: &str, delimiter: char) -> io::Result<Vec<Vec<Option<String>>>> {
+        CsvReader::new(content.as_bytes(), delimiter).collect()
+    }
+
+    fn some(values: &[&str]) -> Vec<Option<String>> {
+        values.iter().map(|value| Some(value.to_string())).collect()
+    }
+
+    #[test]
+    fn plain_records() {
+        let records = records("a,b,c\r\n\n1,2,3\n", ',').unwrap();
+        assert_eq!(
+            records,
+            vec![some(&["a", "b", "c"]), some(&["1", "2", "3"])]
+        );
+    }
+
+    #[test]
+    fn empty_field_is_null_and_quoted_one_is_empty() {
+        let records = records("1,,\"\"\n", ',').unwrap();
+        assert_eq!(records, vec![vec![Some("1".into()), None, Some("".into())]]);
+    }
+
+    #[test]
+    fn quoted_fields() {
+        let records = records("\"a,b\",\"say \"\"hi\"\"\",\"\"\"\"\n", ',').unwrap();
+        assert_eq!(records, vec![some(&["a,b", "say \"hi\"", "\""])]);
+    }
+
+    #[test]
+    fn embedded_line_breaks() {
+        let records = records("1,\"two\nlines\",3\n\"\r\n\"\n", ',').unwrap();
+        assert_eq!(
+            records,
+            vec![some(&["1", "two\nlines", "3"]), some(&["\r\n"])]
+        );
+    }
+
+    #[test]
+    fn unterminated_quote() {
+        let err = records("1,\"open\n", ',').unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn other_delimiter() {
+        let records = records("a;\"b;c\",d\n", ';').unwrap();
+        assert_eq!(records, vec![some(&["a", "b;c,d"])]);
+    }
+
+    #[test]
+    fn fields_written_are_read_back() {
+        let values = ["", "plain", "a,b", "\"quoted\"", "line\nbreak", "cr\r"];
+        for quote in [
+            QuoteStyle::Necessary,
+            QuoteStyle::Always,
+            QuoteStyle::NonNumeric,
+        ] {
+            let options = CsvOptions {
+                quote,
+                ..CsvOptions::default()
+            };
+            let mut line = String::new();
+            for (index, value) in values.iter().enumerate() {
+                if index != 0 {
+                    line.push(',');
+                }
+                push_field(&mut line, value, false, &options);
+            }
+            line.push('\n');
+            assert_eq!(records(&line, ',').unwrap(), vec![some(&values)]);
+        }
+    }
+
+    #[test]
+    fn typed_header() {
+        let mut reader = CsvReader::new(
+            "ts:TIMESTAMP,\"a,b:BINARY(20)\",n:NCHAR(8)\n".as_bytes(),
+            ',',
+        );
+        let columns = read_header(Path::new("t.csv"), "t", &mut reader).unwrap();
+        assert_eq!(
+            columns,
+            vec![
+                ("ts".to_string(), "TIMESTAMP".to_string()),
+                ("a,b".to_string(), "BINARY(20)".to_string()),
+                ("n".to_string(), "NCHAR(8)".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn malformed_header() {
+        for header in ["ts:TIMESTAMP,v\n", "ts:TIMESTAMP,\n", ""] {
+            let mut reader = CsvReader::new(header.as_bytes(), ',');
+            assert!(read_header(Path::new("t.csv"), "t", &mut reader).is_err());
+        }
+    }
+
+    #[test]
+    fn typed_fields() {
+        let ms = TimestampPrecision::Milli;
+        let us = TimestampPrecision::Micro;
+        assert_eq!(
+            parse_field("1000", "TIMESTAMP", ms, us),
+            Some(Field::BigInt(1_000_000))
+        );
+        assert_eq!(
+            parse_field("1970-01-01T00:00:01Z", "TIMESTAMP", ms, us),
+            Some(Field::BigInt(1_000_000))
+        );
+        assert_eq!(
+            parse_field("-128", "TINYINT", ms, ms),
+            Some(Field::TinyInt(-128))
+        );
+        assert_eq!(parse_field("128", "TINYINT", ms, ms), None);
+        assert_eq!(
+            parse_field("18446744073709551615", "BIGINT UNSIGNED", ms, ms),
+            Some(Field::UBigInt(u64::MAX))
+        );
+        assert_eq!(
+            parse_field("a b", "BINARY(20)", ms, ms),
+            Some(Field::Binary(BString::from("a b")))
+        );
+        assert_eq!(
+            parse_field("é", "NCHAR(2)", ms, ms),
+            Some(Field::NChar("é".into()))
+        );
+        assert_eq!(parse_field("x", "DECIMAL", ms, ms), None);
+    }
+
+    #[test]
+    fn varbinary_round_trips_in_hex() {
+        let ms = TimestampPrecision::Milli;
+        let options = CsvOptions::default();
+        let bytes = Field::Binary(BString::from(&b"\x00\n\xff"[..]));
+        let (value, _) = render_field(Path::new("t.csv"), &bytes, "VARBINARY(8)", &options, ms)
+            .unwrap()
+            .unwrap();
+        assert_eq!(value, "\\x000aff");
+        assert_eq!(parse_field(&value, "VARBINARY(8)", ms, ms), Some(bytes));
+        for value in ["000aff", "\\x0", "\\xzz", "\\x+f"] {
+            assert_eq!(parse_field(value, "VARBINARY", ms, ms), None, "{}", value);
+        }
+        let invalid = Field::Binary(BString::from(&b"\xff"[..]));
+        assert!(render_field(Path::new("t.csv"), &invalid, "BINARY(8)", &options, ms).is_err());
+    }
+}
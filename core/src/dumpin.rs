@@ -1,17 +1,608 @@
-use std::path::PathBuf;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
 
-use utils::{error::Result, taos::taos_connect, Formats};
+use bstr::BString;
+use libtaos::stmt::Stmt;
+use libtaos::{Field, Taos, Timestamp, TimestampPrecision};
+use log::{info, trace, warn};
+use utils::error::{Error, Result};
+use utils::taos::{taos_connect, ConnectOptions};
+use utils::{get_all_files, into_inner, join, lock, Formats};
 
+use crate::arrow::arrow_dumpin;
+use crate::csv::{csv_dumpin, CsvOptions};
+use crate::dumpout::column_sql_value;
+use crate::jsonl::{jsonl_dumpin, JsonlOptions};
+use crate::line_protocol::line_protocol_dumpin;
+use crate::manifest::Manifest;
+use crate::sql::sql_dumpin;
+use crate::state::Checkpoint;
+use crate::time::{database_precision, ddl_precision};
+use crate::validate::validate;
 use crate::{avro::avro_dumpin, parquet::parquet_dumpin};
 
-pub fn start(file_list: &Vec<PathBuf>, _thread: u32, format: Formats) -> Result<()> {
-    let taos = taos_connect().unwrap();
-    taos.raw_query("create database if not exists demo")?;
-    taos.raw_query("use demo")?;
-    match format {
-        Formats::Parquet => parquet_dumpin(file_list, taos),
-        Formats::Avro => avro_dumpin(file_list, taos),
-    };
+/// Default number of rows sent in a single INSERT statement.
+pub const DEFAULT_BATCH_ROWS: usize = 1000;
+/// Longest INSERT statement sent to TDengine, its default `maxSQLLength`.
+pub const MAX_SQL_LENGTH: usize = 65480;
 
+/// What to load from the data files and how.
+#[derive(Debug)]
+pub struct DumpInOptions {
+    pub format: Formats,
+    pub batch_rows: usize,
+    /// Layout of CSV data files.
+    pub csv: CsvOptions,
+    /// Layout of JSON Lines data files.
+    pub jsonl: JsonlOptions,
+    /// Skip the files completely loaded by an interrupted run.
+    pub resume: bool,
+    /// Compare the restored tables with the manifest of the dump once every file is loaded.
+    pub validate: bool,
+}
+
+pub fn start(
+    connect_options: &ConnectOptions,
+    dir_path: &str,
+    file_list: &[PathBuf],
+    threads: u32,
+    name: String,
+    options: &DumpInOptions,
+) -> Result<()> {
+    let taos = taos_connect(connect_options)?;
+    let source = source_database(dir_path)?;
+    restore_database(&taos, dir_path, &source, &name)?;
+    // schemaless insert creates the tables of line protocol files itself, with tags of its own
+    if !matches!(options.format, Formats::LineProtocol) {
+        restore_tables(&taos, dir_path)?;
+    }
+    let source_precision = source_precision(dir_path, &source)?;
+    let checkpoint = Mutex::new(Checkpoint::open(
+        Checkpoint::path(dir_path, &name, "dumpin"),
+        options.resume,
+    )?);
+
+    // workers take the next file to load from a shared cursor
+    let next_file = AtomicUsize::new(0);
+    let workers = (threads.max(1) as usize).min(file_list.len());
+    info!("dump in with {} workers", workers);
+    let results = thread::scope(|scope| {
+        let handles = (0..workers)
+            .map(|_| {
+                scope.spawn(|| {
+                    dumpin_worker(
+                        connect_options,
+                        &name,
+                        file_list,
+                        &next_file,
+                        source_precision,
+                        options,
+                        &checkpoint,
+                    )
+                })
+            })
+            .collect::<Vec<_>>();
+        handles.into_iter().map(join).collect::<Vec<_>>()
+    });
+    for result in results {
+        result?;
+    }
+    into_inner(checkpoint, "checkpoint")?.clear()?;
+
+    if options.validate {
+        validate(&taos, dir_path, &source, &name, file_list)?;
+    }
+    Ok(())
+}
+
+/// Load files until none is left, using a connection of its own.
+#[tokio::main]
+async fn dumpin_worker(
+    connect_options: &ConnectOptions,
+    name: &str,
+    file_list: &[PathBuf],
+    next_file: &AtomicUsize,
+    source: TimestampPrecision,
+    options: &DumpInOptions,
+    checkpoint: &Mutex<Checkpoint>,
+) -> Result<()> {
+    let taos = taos_connect(connect_options)?;
+    taos.use_database(name)
+        .await
+        .map_err(|err| Error::query(format!("use {}", name), err))?;
+    let precision = database_precision(&taos, name)?;
+    loop {
+        let index = next_file.fetch_add(1, Ordering::Relaxed);
+        let file = match file_list.get(index) {
+            Some(file) => file,
+            None => break,
+        };
+        if lock(checkpoint, "checkpoint")?.is_done(file) {
+            trace!("{} is already dumped in, skipped", file.display());
+            continue;
+        }
+        lock(checkpoint, "checkpoint")?.start(file, table_name(file)?)?;
+        match options.format {
+            Formats::Parquet => parquet_dumpin(&taos, file, options.batch_rows, precision).await?,
+            Formats::Avro => {
+                avro_dumpin(&taos, file, options.batch_rows, source, precision).await?
+            }
+            Formats::Csv => {
+                let csv = &options.csv;
+                csv_dumpin(&taos, file, options.batch_rows, csv, source, precision).await?
+            }
+            Formats::Arrow => arrow_dumpin(&taos, file, options.batch_rows, precision).await?,
+            Formats::Sql => sql_dumpin(&taos, file).await?,
+            Formats::Jsonl => {
+                let jsonl = &options.jsonl;
+                jsonl_dumpin(&taos, file, options.batch_rows, jsonl, source, precision).await?
+            }
+            Formats::LineProtocol => line_protocol_dumpin(&taos, file, options.batch_rows).await?,
+        };
+        lock(checkpoint, "checkpoint")?.finish(file)?;
+        trace!("{} is dumped in", file.display());
+    }
     Ok(())
 }
+
+/// Name of the table a data file is restored into, its file stem up to the mark of an
+/// incremental dump.
+pub fn table_name(file: &Path) -> Result<&str> {
+    file.file_stem()
+        .and_then(|stem| stem.to_str())
+        .and_then(|stem| stem.split('.').next())
+        .ok_or_else(|| Error::decode(file, "file name is not a valid table name"))
+}
+
+/// Length of the string columns of data files that do not record their declared one.
+pub const DEFAULT_STRING_LENGTH: usize = 8;
+
+/// Whether a column described as `data_type` holds strings or bytes, which data files dump out
+/// alike, as bytes, and the types of TDengine 3 among them as text.
+pub fn is_string_type(data_type: &str) -> bool {
+    matches!(
+        split_length(data_type).0,
+        "BINARY" | "VARCHAR" | "NCHAR" | "VARBINARY" | "GEOMETRY" | "JSON"
+    )
+}
+
+/// Whether the column at `index` may hold NULL in a data file: every column but the primary
+/// timestamp, which TDengine never leaves empty.
+pub fn is_nullable(index: usize) -> bool {
+    index != 0
+}
+
+/// Whether a column described as `data_type` holds bytes rather than text, as VARBINARY
+/// values and the WKB of GEOMETRY ones do.
+pub fn is_bytes_type(data_type: &str) -> bool {
+    matches!(split_length(data_type).0, "VARBINARY" | "GEOMETRY")
+}
+
+/// Whether libtaos can fetch and bind values of a column described as `data_type`, as it
+/// predates the VARBINARY, GEOMETRY and JSON types of TDengine 3.
+pub fn is_native_type(data_type: &str) -> bool {
+    !matches!(split_length(data_type).0, "VARBINARY" | "GEOMETRY" | "JSON")
+}
+
+/// Declared types of the columns, as a JSON object by column name, for the data formats whose
+/// own types cannot tell every TDengine type apart, such as TINYINT from INT or BINARY from
+/// VARBINARY.
+pub fn declared_types(column_names: &[String], data_types: &[BString]) -> String {
+    let types = column_names
+        .iter()
+        .zip(data_types)
+        .map(|(name, data_type)| {
+            (
+                name.clone(),
+                serde_json::Value::String(data_type.to_string()),
+            )
+        })
+        .collect();
+    serde_json::Value::Object(types).to_string()
+}
+
+/// Declared types recorded by [`declared_types`], none for files dumped before they were.
+pub fn read_declared_types(text: Option<&str>) -> HashMap<String, String> {
+    text.and_then(|text| serde_json::from_str(text).ok())
+        .unwrap_or_default()
+}
+
+/// Type of a column as recorded by a data file and its declared length, `BINARY(20)` giving
+/// `("BINARY", Some(20))`.
+pub fn split_length(data_type: &str) -> (&str, Option<usize>) {
+    let declared = data_type
+        .strip_suffix(')')
+        .and_then(|data_type| data_type.split_once('('))
+        .and_then(|(data_type, length)| Some((data_type.trim_end(), length.trim().parse().ok()?)));
+    match declared {
+        Some((data_type, length)) => (data_type, Some(length)),
+        None => (data_type, None),
+    }
+}
+
+/// Column type of the `CREATE TABLE` statement of a column described as `data_type`.
+pub fn column_sql_type(table: &str, column: &str, data_type: &str) -> Result<String> {
+    match split_length(data_type) {
+        (
+            "TIMESTAMP" | "BOOL" | "TINYINT" | "SMALLINT" | "INT" | "BIGINT" | "FLOAT" | "DOUBLE"
+            | "TINYINT UNSIGNED" | "SMALLINT UNSIGNED" | "INT UNSIGNED" | "BIGINT UNSIGNED",
+            None,
+        ) => Ok(data_type.to_lowercase()),
+        (data_type @ ("BINARY" | "VARCHAR" | "NCHAR" | "VARBINARY" | "GEOMETRY"), length) => {
+            Ok(format!(
+                "{}({})",
+                data_type.to_lowercase(),
+                length.unwrap_or(DEFAULT_STRING_LENGTH)
+            ))
+        }
+        ("JSON", None) => Ok("json".to_string()),
+        _ => Err(Error::unsupported_type(table, column, data_type)),
+    }
+}
+
+/// `CREATE TABLE` statement of a table with the given column names and described types, for
+/// the data formats that record them as text.
+pub fn create_table_sql(table: &str, columns: &[(String, String)]) -> Result<String> {
+    let mut sql = format!("create table if not exists {} (", table);
+    for (index, (name, data_type)) in columns.iter().enumerate() {
+        if index != 0 {
+            sql += ",";
+        }
+        sql += &format!("{} {}", name, column_sql_type(table, name, data_type)?);
+    }
+    sql += ")";
+    Ok(sql)
+}
+
+/// Loads rows into a table, `batch_rows` at a time, each value converted to the type of its
+/// column first. Rows are bound to a prepared `INSERT` statement, or rendered into
+/// `INSERT INTO <table> VALUES ...` statements, sent before they would outgrow
+/// [`MAX_SQL_LENGTH`], when the connection cannot prepare one.
+pub struct InsertBatch<'a> {
+    taos: &'a Taos,
+    table: &'a str,
+    /// Data file the rows are loaded from, reported when an INSERT is rejected.
+    file: &'a Path,
+    /// Names and described types of the columns of the table.
+    columns: Vec<(String, String)>,
+    precision: TimestampPrecision,
+    stmt: Option<Stmt>,
+    /// The prepared statement, or the head of the rendered ones.
+    head: String,
+    batch_rows: usize,
+    sql: String,
+    rows: usize,
+    /// Rows pushed so far, to locate an invalid value.
+    pushed: usize,
+}
+
+impl<'a> InsertBatch<'a> {
+    pub async fn new(
+        taos: &'a Taos,
+        table: &'a str,
+        file: &'a Path,
+        batch_rows: usize,
+        precision: TimestampPrecision,
+    ) -> Result<InsertBatch<'a>> {
+        let sql = format!("describe {}", table);
+        let rows = taos
+            .query(&sql)
+            .await
+            .map_err(|err| Error::query(&sql, err))?;
+        // tags of a child table are set by its super table
+        let columns = rows
+            .rows
+            .iter()
+            .filter(|row| row[3].to_string() != "TAG")
+            .map(|row| (row[0].to_string(), row[1].to_string()))
+            .collect::<Vec<_>>();
+
+        let prepared = format!(
+            "insert into {} values({})",
+            table,
+            vec!["?"; columns.len()].join(",")
+        );
+        let sql_head = format!("insert into {} values", table);
+        // values libtaos cannot bind are inserted as SQL literals
+        let (stmt, head) = if columns
+            .iter()
+            .any(|(_, data_type)| !is_native_type(data_type))
+        {
+            (None, sql_head)
+        } else {
+            match taos.stmt(&prepared) {
+                Ok(stmt) => (Some(stmt), prepared),
+                Err(err) => {
+                    warn!(
+                        "cannot prepare {}, rows are inserted as SQL: {}",
+                        prepared, err
+                    );
+                    (None, sql_head)
+                }
+            }
+        };
+        Ok(InsertBatch {
+            taos,
+            table,
+            file,
+            columns,
+            precision,
+            stmt,
+            sql: head.clone(),
+            head,
+            batch_rows: batch_rows.max(1),
+            rows: 0,
+            pushed: 0,
+        })
+    }
+
+    /// Add the values of one row, in column order.
+    pub async fn push(&mut self, row: Vec<Field>) -> Result<()> {
+        self.pushed += 1;
+        if row.len() != self.columns.len() {
+            return Err(Error::decode(
+                self.file,
+                format!(
+                    "row {} has {} values, table {} has {} columns",
+                    self.pushed,
+                    row.len(),
+                    self.table,
+                    self.columns.len()
+                ),
+            ));
+        }
+        let mut values = Vec::with_capacity(row.len());
+        for (field, (name, data_type)) in row.iter().zip(&self.columns) {
+            let value = column_value(field, data_type, self.precision).ok_or_else(|| {
+                Error::decode(
+                    self.file,
+                    format!(
+                        "value {} of column {} in row {} does not fit type {}",
+                        field, name, self.pushed, data_type
+                    ),
+                )
+            })?;
+            values.push(value);
+        }
+
+        match &mut self.stmt {
+            Some(stmt) => {
+                if let Err(source) = stmt.bind(values.iter()) {
+                    return Err(self.rejected(source.into()));
+                }
+            }
+            None => {
+                let mut literals = Vec::with_capacity(values.len());
+                for (value, (name, data_type)) in values.iter().zip(&self.columns) {
+                    let literal = column_sql_value(value, data_type).ok_or_else(|| {
+                        Error::decode(
+                            self.file,
+                            format!(
+                                "value of column {} in row {} has no SQL form",
+                                name, self.pushed
+                            ),
+                        )
+                    })?;
+                    literals.push(literal);
+                }
+                let values = format!("({})", literals.join(","));
+                if self.rows > 0 && self.sql.len() + values.len() > MAX_SQL_LENGTH {
+                    self.flush().await?;
+                }
+                self.sql += &values;
+            }
+        }
+        self.rows += 1;
+        if self.rows >= self.batch_rows {
+            self.flush().await?;
+        }
+        Ok(())
+    }
+
+    /// Send the buffered rows, if any.
+    pub async fn flush(&mut self) -> Result<()> {
+        if self.rows == 0 {
+            return Ok(());
+        }
+        let result = match &self.stmt {
+            Some(stmt) => stmt.execute().map_err(libtaos::Error::from),
+            None => self.taos.query(&self.sql).await.map(|_| ()),
+        };
+        if let Err(source) = result {
+            return Err(self.rejected(source));
+        }
+        self.sql.truncate(self.head.len());
+        self.rows = 0;
+        Ok(())
+    }
+
+    fn rejected(&self, source: libtaos::Error) -> Error {
+        Error::InsertRejected {
+            table: self.table.to_string(),
+            file: self.file.to_path_buf(),
+            sql: self.sql.clone(),
+            source,
+        }
+    }
+}
+
+/// `field` as a value of a column described as `data_type`, `None` when it does not fit, as a
+/// prepared statement only takes values of the exact type of their column.
+fn column_value(field: &Field, data_type: &str, precision: TimestampPrecision) -> Option<Field> {
+    if *field == Field::Null {
+        return Some(Field::Null);
+    }
+    Some(match data_type {
+        "TIMESTAMP" => {
+            Field::Timestamp(Timestamp::new(integer(field)?.try_into().ok()?, precision))
+        }
+        "BOOL" => match field {
+            Field::Bool(v) => Field::Bool(*v),
+            _ => return None,
+        },
+        "TINYINT" => Field::TinyInt(integer(field)?.try_into().ok()?),
+        "SMALLINT" => Field::SmallInt(integer(field)?.try_into().ok()?),
+        "INT" => Field::Int(integer(field)?.try_into().ok()?),
+        "BIGINT" => Field::BigInt(integer(field)?.try_into().ok()?),
+        "TINYINT UNSIGNED" => Field::UTinyInt(integer(field)?.try_into().ok()?),
+        "SMALLINT UNSIGNED" => Field::USmallInt(integer(field)?.try_into().ok()?),
+        "INT UNSIGNED" => Field::UInt(integer(field)?.try_into().ok()?),
+        "BIGINT UNSIGNED" => Field::UBigInt(integer(field)?.try_into().ok()?),
+        "FLOAT" => Field::Float(match field {
+            Field::Float(v) => *v,
+            Field::Double(v) => *v as f32,
+            field => integer(field)? as f32,
+        }),
+        "DOUBLE" => Field::Double(match field {
+            Field::Double(v) => *v,
+            // through the shortest decimal of the f32, as a SQL literal would
+            Field::Float(v) => v.to_string().parse().ok()?,
+            field => integer(field)? as f64,
+        }),
+        // bytes are inserted in hex or as WKT, text would end early at a NUL byte
+        "VARBINARY" | "GEOMETRY" => match field {
+            Field::Binary(v) => Field::Binary(v.clone()),
+            Field::NChar(v) => Field::Binary(BString::from(v.as_str())),
+            _ => return None,
+        },
+        "BINARY" | "VARCHAR" | "JSON" => match field {
+            Field::Binary(v) if !v.contains(&0) => Field::Binary(v.clone()),
+            Field::NChar(v) if !v.contains('\0') => Field::Binary(BString::from(v.as_str())),
+            _ => return None,
+        },
+        "NCHAR" => match field {
+            Field::NChar(v) if !v.contains('\0') => Field::NChar(v.clone()),
+            Field::Binary(v) if !v.contains(&0) => {
+                Field::NChar(String::from_utf8(v.to_vec()).ok()?)
+            }
+            _ => return None,
+        },
+        _ => return None,
+    })
+}
+
+/// Value of an integer or timestamp field.
+fn integer(field: &Field) -> Option<i128> {
+    Some(match field {
+        Field::TinyInt(v) => *v as i128,
+        Field::SmallInt(v) => *v as i128,
+        Field::Int(v) => *v as i128,
+        Field::BigInt(v) => *v as i128,
+        Field::UTinyInt(v) => *v as i128,
+        Field::USmallInt(v) => *v as i128,
+        Field::UInt(v) => *v as i128,
+        Field::UBigInt(v) => *v as i128,
+        Field::Timestamp(v) => v.as_raw_timestamp() as i128,
+        _ => return None,
+    })
+}
+
+/// Name of the database dumped into `dir_path`, told by the `<source>.db` DDL file of dumpout.
+pub fn source_database(dir_path: &str) -> Result<String> {
+    let names = get_all_files(dir_path, "db")?
+        .iter()
+        .filter_map(|path| path.file_stem())
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .collect::<Vec<_>>();
+    match names.as_slice() {
+        [source] => Ok(source.clone()),
+        [] => Err(Error::io(
+            dir_path,
+            io::Error::new(io::ErrorKind::NotFound, "no database DDL in dump directory"),
+        )),
+        sources => Err(Error::io(
+            dir_path,
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "DDL of several databases in dump directory: {}",
+                    sources.join(", ")
+                ),
+            ),
+        )),
+    }
+}
+
+/// Timestamp precision of the dumped database `source`, which bare epochs of the data files are
+/// in: recorded by the manifest of recent dumps, or else by the `<source>.db` DDL.
+pub fn source_precision(dir_path: &str, source: &str) -> Result<TimestampPrecision> {
+    if let Some(precision) =
+        Manifest::read(dir_path, source)?.and_then(|manifest| manifest.precision)
+    {
+        return Ok(precision);
+    }
+    let path = Path::new(dir_path).join(format!("{}.db", source));
+    let ddl = fs::read_to_string(&path).map_err(|err| Error::io(&path, err))?;
+    ddl_precision(&ddl).ok_or_else(|| {
+        Error::schema(
+            source,
+            format!(
+                "no timestamp precision in the manifest or in {}",
+                path.display()
+            ),
+        )
+    })
+}
+
+/// Recreate database `name` from the `<source>.db` DDL file written by dumpout, and switch to it.
+fn restore_database(taos: &Taos, dir_path: &str, source: &str, name: &str) -> Result<()> {
+    let path = Path::new(dir_path).join(format!("{}.db", source));
+    let ddl = fs::read_to_string(&path).map_err(|err| Error::io(&path, err))?;
+    let sql = create_database_sql(&ddl, name);
+    info!("restore database: {}", sql);
+    taos.raw_query(&sql)
+        .map_err(|err| Error::query(&sql, err))?;
+    let sql = format!("use {}", name);
+    taos.raw_query(&sql)
+        .map_err(|err| Error::query(&sql, err))?;
+    Ok(())
+}
+
+/// Recreate the super tables and child tables from the DDL files written by dumpout.
+fn restore_tables(taos: &Taos, dir_path: &str) -> Result<()> {
+    // super tables must exist before their child tables
+    for extension in ["stb", "ctb"] {
+        for path in get_all_files(dir_path, extension)? {
+            let ddls = fs::read_to_string(&path).map_err(|err| Error::io(&path, err))?;
+            for ddl in ddls.lines() {
+                if ddl.trim().is_empty() {
+                    continue;
+                }
+                let sql = with_if_not_exists(ddl);
+                taos.raw_query(&sql)
+                    .map_err(|err| Error::query(&sql, err))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Rewrite the saved `CREATE DATABASE <source> <options>` so it creates `name` with the same options.
+fn create_database_sql(ddl: &str, name: &str) -> String {
+    let ddl = with_if_not_exists(ddl);
+    let mut parts = ddl.splitn(7, ' ');
+    let head = parts.by_ref().take(5).collect::<Vec<_>>().join(" ");
+    // skip the source database name
+    parts.next();
+    let options = parts.next().unwrap_or("");
+    format!("{} {} {}", head, name, options)
+}
+
+/// Make a `CREATE <kind> <name> ...` statement a no-op when the object already exists.
+pub fn with_if_not_exists(ddl: &str) -> String {
+    let ddl = ddl.trim();
+    let mut parts = ddl.splitn(3, ' ');
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some(create), Some(kind), Some(rest))
+            if !rest.to_uppercase().starts_with("IF NOT EXISTS") =>
+        {
+            format!("{} {} IF NOT EXISTS {}", create, kind, rest)
+        }
+        _ => ddl.to_string(),
+    }
+}
@@ -1,63 +1,733 @@
+use bstr::BString;
 use libtaos::*;
 use log::{info, trace};
 use utils::Formats;
 
+use std::collections::HashSet;
 use std::io::Write;
-use std::{fs, path::Path};
-use utils::error::Result;
-use utils::taos::taos_connect;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::{fs, path::Path, thread};
+use utils::error::{Error, Result};
+use utils::taos::{taos_connect, ConnectOptions};
+use utils::{data_file_path, incremental_data_file_path, into_inner, join, lock};
 
+use crate::arrow::arrow_dumpout;
 use crate::avro::avro_dumpout;
-use crate::parquet::parquet_dumpout;
+use crate::csv::{csv_dumpout, CsvOptions};
+use crate::dumpin::{is_native_type, split_length};
+use crate::jsonl::{jsonl_dumpout, JsonlOptions};
+use crate::line_protocol::line_protocol_dumpout;
+use crate::manifest::{numeric_value, ColumnAggregate, Manifest, Summary};
+use crate::parquet::{parquet_dumpout, RowGroupSize};
+use crate::sql::{sql_dumpout, table_ddl, SchemaDdl};
+use crate::state::{Checkpoint, HighWaterMarks};
+use crate::time::{database_precision, TimeRange};
+use crate::wkb::{wkb_to_wkt, wkt_to_wkb};
 
+/// Number of rows fetched from TDengine per query while dumping a table.
+pub const FETCH_BATCH_ROWS: usize = 10000;
+
+/// A child or normal table of the dumped database.
+#[derive(Debug)]
+pub struct Table {
+    pub name: String,
+    /// Super table the table was created from, `None` for normal tables.
+    pub stable: Option<String>,
+    /// Tag names and values of a child table.
+    pub tags: Vec<(String, Field)>,
+    /// Described types of the tags, in the order of `tags`.
+    pub tag_types: Vec<String>,
+}
+
+/// What to dump out of every table and how.
+#[derive(Debug)]
+pub struct DumpOutOptions {
+    pub format: Formats,
+    pub row_group_size: RowGroupSize,
+    /// Layout of CSV data files.
+    pub csv: CsvOptions,
+    /// Layout of JSON Lines data files.
+    pub jsonl: JsonlOptions,
+    pub time_range: TimeRange,
+    /// Only dump rows newer than those of the previous incremental dump, into new files.
+    pub incremental: bool,
+    /// Skip the tables completely dumped by an interrupted run.
+    pub resume: bool,
+}
+
+/// State shared by the workers of one dump.
+struct DumpContext<'a> {
+    dir_path: &'a str,
+    name: &'a str,
+    options: &'a DumpOutOptions,
+    precision: TimestampPrecision,
+    /// DDL heading the files of a SQL dump.
+    ddl: SchemaDdl,
+    marks: Mutex<HighWaterMarks>,
+    checkpoint: Mutex<Checkpoint>,
+    manifest: Mutex<Manifest>,
+}
+
+#[tokio::main]
+pub async fn start(
+    connect_options: &ConnectOptions,
+    dir_path: &str,
+    threads: u32,
+    name: String,
+    stables: &[String],
+    options: &DumpOutOptions,
+) -> Result<i64> {
+    let taos = taos_connect(connect_options)?;
+    let precision = database_precision(&taos, &name)?;
+
+    let tables = list_tables(&taos, &name, stables).await?;
+    info!("found {} tables in database {}", tables.len(), name);
+    // schemaless insert would restore a normal table as a super table of its own
+    if matches!(options.format, Formats::LineProtocol) {
+        if let Some(table) = tables.iter().find(|table| table.stable.is_none()) {
+            return Err(Error::schema(
+                &table.name,
+                "normal tables cannot be dumped out as line protocol",
+            ));
+        }
+    }
+    dumpout_child_table_sql(dir_path, &name, &tables)?;
+
+    let marks = if options.incremental {
+        HighWaterMarks::load(dir_path, &name)?
+    } else {
+        HighWaterMarks::default()
+    };
+    let checkpoint =
+        Checkpoint::open(Checkpoint::path(dir_path, &name, "dumpout"), options.resume)?;
+    // incremental and resumed dumps add files to those of earlier runs
+    let mut manifest = Manifest::open(dir_path, &name, options.incremental || options.resume)?;
+    manifest.precision = Some(precision);
+    manifest.save()?;
+    let ddl = match options.format {
+        Formats::Sql => SchemaDdl::load(dir_path, &name)?,
+        _ => SchemaDdl::default(),
+    };
+    let context = DumpContext {
+        dir_path,
+        name: &name,
+        options,
+        precision,
+        ddl,
+        marks: Mutex::new(marks),
+        checkpoint: Mutex::new(checkpoint),
+        manifest: Mutex::new(manifest),
+    };
+
+    // workers take the next table to dump from a shared cursor
+    let next_table = AtomicUsize::new(0);
+    let workers = (threads.max(1) as usize).min(tables.len());
+    info!("dump out with {} workers", workers);
+    let results = thread::scope(|scope| {
+        let handles = (0..workers)
+            .map(|_| {
+                scope.spawn(|| dumpout_worker(connect_options, &context, &tables, &next_table))
+            })
+            .collect::<Vec<_>>();
+        handles.into_iter().map(join).collect::<Vec<_>>()
+    });
+    // keep the marks and manifest entries of the tables that were dumped even if others failed
+    if options.incremental {
+        into_inner(context.marks, "high-water marks")?.save(dir_path, &name)?;
+    }
+    into_inner(context.manifest, "manifest")?.save()?;
+
+    let mut num_of_points = 0;
+    for result in results {
+        num_of_points += result?;
+    }
+    into_inner(context.checkpoint, "checkpoint")?.clear()?;
+    Ok(num_of_points)
+}
+
+/// Dump tables until none is left, using a connection of its own.
 #[tokio::main]
-pub async fn start(dir_path: &str, _threads: u32, format: Formats) -> Result<i64> {
-    let taos = taos_connect().unwrap();
+async fn dumpout_worker(
+    connect_options: &ConnectOptions,
+    context: &DumpContext<'_>,
+    tables: &[Table],
+    next_table: &AtomicUsize,
+) -> Result<i64> {
+    let taos = taos_connect(connect_options)?;
+    let mut num_of_points = 0;
+    loop {
+        let index = next_table.fetch_add(1, Ordering::Relaxed);
+        let table = match tables.get(index) {
+            Some(table) => table,
+            None => break,
+        };
+        if lock(&context.checkpoint, "checkpoint")?.is_table_done(&table.name) {
+            trace!("table {} is already dumped out, skipped", table.name);
+            continue;
+        }
+        num_of_points += dumpout_table(&taos, context, table).await?;
+    }
+    Ok(num_of_points)
+}
+
+/// Version of the TDengine server, such as `3.0.4.0`.
+async fn server_version(taos: &Taos) -> Result<String> {
+    let sql = "select server_version()";
+    let rows = taos
+        .query(sql)
+        .await
+        .map_err(|err| Error::query(sql, err))?;
+    Ok(rows
+        .rows
+        .first()
+        .and_then(|row| row.first())
+        .map(|version| version.to_string())
+        .unwrap_or_default())
+}
+
+/// Enumerate the child tables of every super table and all normal tables of database `name`.
+pub async fn list_tables(taos: &Taos, name: &str, stables: &[String]) -> Result<Vec<Table>> {
+    // TDengine 3 returns the tags of a child table with each of its rows, and none for a child
+    // table without rows, unless they are selected with TAGS
+    let select = match server_version(taos).await?.starts_with("3.") {
+        true => "select tags tbname",
+        false => "select tbname",
+    };
+    let mut tables = vec![];
+    for stable in stables {
+        let mut tag_names = vec![];
+        let mut tag_types = vec![];
+        let mut sql = select.to_string();
+        let describe = format!("describe {}.{}", name, stable);
+        let rows = taos
+            .query(&describe)
+            .await
+            .map_err(|err| Error::query(&describe, err))?;
+        for row in rows.rows {
+            if row[3].to_string() == "TAG" {
+                let tag = row[0].to_string();
+                sql += ",";
+                sql += &select_expression(stable, &tag, &row[1].to_string(), &row[2])?;
+                tag_names.push(tag);
+                tag_types.push(row[1].to_string());
+            }
+        }
 
+        sql += &format!(" from {}.{}", name, stable);
+        let rows = taos
+            .query(&sql)
+            .await
+            .map_err(|err| Error::query(&sql, err))?;
+        for mut row in rows.rows {
+            let tags = tag_names.iter().cloned().zip(row.drain(1..)).collect();
+            tables.push(Table {
+                name: row[0].to_string(),
+                stable: Some(stable.clone()),
+                tags,
+                tag_types: tag_types.clone(),
+            });
+        }
+    }
+
+    let sql = format!("show {}.tables", name);
+    let rows = taos
+        .query(&sql)
+        .await
+        .map_err(|err| Error::query(&sql, err))?;
+    let stable_index = rows
+        .column_meta
+        .iter()
+        .position(|meta| meta.name == "stable_name");
+    for row in rows.rows {
+        let is_normal = match stable_index {
+            Some(index) => row[index] == Field::Null || row[index].to_string().is_empty(),
+            None => true,
+        };
+        if is_normal {
+            tables.push(Table {
+                name: row[0].to_string(),
+                stable: None,
+                tags: vec![],
+                tag_types: vec![],
+            });
+        }
+    }
+    // a table listed twice would be dumped by two workers into the same files
+    let mut names = HashSet::new();
+    tables.retain(|table| names.insert(table.name.clone()));
+    Ok(tables)
+}
+
+/// Dump a `CREATE TABLE ... USING ... TAGS (...)` statement for every child table.
+pub fn dumpout_child_table_sql(dir_path: &str, name: &str, tables: &[Table]) -> Result<()> {
+    let path = Path::new(dir_path).join(format!("{}.ctb", name));
+    let mut file = fs::File::create(&path).map_err(|err| Error::io(&path, err))?;
+    let mut count = 0;
+    for table in tables {
+        let stable = match &table.stable {
+            Some(stable) => stable,
+            None => continue,
+        };
+        let names = table
+            .tags
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<_>>();
+        let mut values = vec![];
+        for ((tag, value), data_type) in table.tags.iter().zip(&table.tag_types) {
+            values.push(column_sql_value(value, data_type).ok_or_else(|| {
+                Error::encode(
+                    &path,
+                    format!(
+                        "value of tag {} of table {} has no SQL form",
+                        tag, table.name
+                    ),
+                )
+            })?);
+        }
+        writeln!(
+            file,
+            "CREATE TABLE {} USING {} ({}) TAGS ({})",
+            table.name,
+            stable,
+            names.join(","),
+            values.join(",")
+        )
+        .map_err(|err| Error::io(&path, err))?;
+        count += 1;
+    }
+    info!(
+        "{} child tables of database {} are successfully dumped out to {}",
+        count,
+        name,
+        path.display()
+    );
+    Ok(())
+}
+
+/// Render a field as a TDengine SQL literal, `None` for bytes that are not UTF-8 and for NaN
+/// and infinite floats, which SQL has no literal for.
+pub fn sql_value(field: &Field) -> Option<String> {
+    Some(match field {
+        Field::Null => "NULL".to_string(),
+        Field::Binary(v) => quote(std::str::from_utf8(v).ok()?),
+        Field::NChar(v) => quote(v),
+        Field::Timestamp(v) => v.as_raw_timestamp().to_string(),
+        Field::Float(v) if !v.is_finite() => return None,
+        Field::Double(v) if !v.is_finite() => return None,
+        _ => field.to_string(),
+    })
+}
+
+/// `field` as a SQL literal for a column of type `data_type`, the bytes of a VARBINARY value
+/// in hex and the WKB of a GEOMETRY one as WKT, `None` for values a statement cannot carry,
+/// as [`sql_value`] tells.
+pub fn column_sql_value(field: &Field, data_type: &str) -> Option<String> {
+    match (field, split_length(data_type).0) {
+        (Field::Binary(v), "VARBINARY") => Some(format!("'{}'", hex(v))),
+        (Field::Binary(v), "GEOMETRY") => wkb_to_wkt(v).map(|wkt| quote(&wkt)),
+        (field, _) => sql_value(field),
+    }
+}
+
+/// `bytes` as `\x` followed by two hex digits a byte, the text TDengine takes for VARBINARY.
+pub fn hex(bytes: &[u8]) -> String {
+    let mut hex = String::with_capacity(2 + 2 * bytes.len());
+    hex.push_str("\\x");
+    for byte in bytes {
+        hex.push_str(&format!("{:02x}", byte));
+    }
+    hex
+}
+
+fn quote(value: &str) -> String {
+    format!("'{}'", value.replace('\\', "\\\\").replace('\'', "\\'"))
+}
+
+/// Described type of a column, with the declared length of a string column, as in
+/// `BINARY(20)`, so that dumpin creates it as wide as it was.
+fn declared_type(data_type: BString, length: &Field) -> BString {
+    match (data_type.as_slice(), length) {
+        (b"BINARY" | b"VARCHAR" | b"NCHAR" | b"VARBINARY" | b"GEOMETRY", Field::Int(length)) => {
+            BString::from(format!("{}({})", data_type, length))
+        }
+        _ => data_type,
+    }
+}
+
+/// Expression selecting column `name` of `table`, of type `data_type` and `length` long as
+/// described. The types libtaos cannot fetch are selected as text: GEOMETRY as WKT, turned into
+/// WKB once fetched, VARBINARY and JSON cast to a VARCHAR as long as the column, so that no
+/// value is cut.
+fn select_expression(table: &str, name: &str, data_type: &str, length: &Field) -> Result<String> {
+    match (split_length(data_type).0, length) {
+        _ if is_native_type(data_type) => Ok(name.to_string()),
+        ("GEOMETRY", _) => Ok(format!("st_astext({})", name)),
+        (_, Field::Int(length)) => Ok(format!("cast({} as varchar({}))", name, length)),
+        _ => Err(Error::schema(
+            table,
+            format!("no length of column {} in describe", name),
+        )),
+    }
+}
+
+/// Columns selected as text that are dumped out as bytes, by index and name.
+#[derive(Debug, Default)]
+pub struct TextColumns {
+    /// VARBINARY columns, their lengths selected after the columns for [`check_lengths`].
+    pub varbinary: Vec<(usize, String)>,
+    /// GEOMETRY columns, selected as WKT for [`geometry_wkb`].
+    pub geometry: Vec<(usize, String)>,
+}
+
+/// Select list of the columns of `table`, as `(name, type, described length)`, followed by
+/// the `length()` of every VARBINARY column.
+fn select_list(table: &str, columns: &[(String, String, Field)]) -> Result<(String, TextColumns)> {
+    let mut expressions = vec![];
+    let mut text_columns = TextColumns::default();
+    for (index, (name, data_type, length)) in columns.iter().enumerate() {
+        expressions.push(select_expression(table, name, data_type, length)?);
+        match split_length(data_type).0 {
+            "VARBINARY" => text_columns.varbinary.push((index, name.clone())),
+            "GEOMETRY" => text_columns.geometry.push((index, name.clone())),
+            _ => {}
+        }
+    }
+    for (_, name) in &text_columns.varbinary {
+        expressions.push(format!("length({})", name));
+    }
+    Ok((expressions.join(","), text_columns))
+}
+
+/// Fail when libtaos cut a VARBINARY value of `row` at a NUL byte, its length falling short of
+/// the one selected after the columns, and drop those lengths.
+fn check_lengths(table: &str, row: &mut Vec<Field>, varbinary: &[(usize, String)]) -> Result<()> {
+    let lengths = row.split_off(row.len().saturating_sub(varbinary.len()));
+    for ((index, name), length) in varbinary.iter().zip(&lengths) {
+        if let (Field::Binary(v), Some(length)) = (&row[*index], numeric_value(length)) {
+            if v.len() as f64 != length {
+                return Err(Error::schema(
+                    table,
+                    format!(
+                        "a value of VARBINARY column {} holds a NUL byte, which libtaos cannot fetch",
+                        name
+                    ),
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Replace the WKT of the GEOMETRY values of `row` with their WKB.
+fn geometry_wkb(table: &str, row: &mut [Field], geometry: &[(usize, String)]) -> Result<()> {
+    for (index, name) in geometry {
+        let wkt = match &row[*index] {
+            Field::Binary(v) => std::str::from_utf8(v).ok(),
+            Field::NChar(v) => Some(v.as_str()),
+            _ => continue,
+        };
+        let wkb = wkt.and_then(wkt_to_wkb).ok_or_else(|| {
+            Error::schema(
+                table,
+                format!("a value of GEOMETRY column {} is not WKT", name),
+            )
+        })?;
+        row[*index] = Field::Binary(BString::from(wkb));
+    }
+    Ok(())
+}
+
+async fn dumpout_table(taos: &Taos, context: &DumpContext<'_>, table: &Table) -> Result<i64> {
+    let DumpContext {
+        dir_path,
+        name,
+        options,
+        precision,
+        ddl,
+        marks,
+        checkpoint,
+        manifest,
+    } = context;
+    let mut column_names = vec![];
     let mut data_types = vec![];
-    let rows = taos.query("describe demo.m1").await.unwrap();
+    let mut lengths = vec![];
+    let sql = format!("describe {}.{}", name, table.name);
+    let rows = taos
+        .query(&sql)
+        .await
+        .map_err(|err| Error::query(&sql, err))?;
     for row in rows.rows {
+        // tags are read with the table list, not with its rows
+        if row[3].to_string() == "TAG" {
+            continue;
+        }
         match row[1].clone() {
             Field::Binary(v) => {
-                data_types.push(v);
+                column_names.push(row[0].to_string());
+                data_types.push(declared_type(v, &row[2]));
+                lengths.push(row[2].clone());
             }
-            _ => {
-                unreachable!("unexpected data type, please contact the author to fix!");
+            field => {
+                return Err(Error::schema(
+                    &table.name,
+                    format!("unexpected type {} of column {} in describe", field, row[0]),
+                ));
             }
         }
     }
+    let ts_column = match column_names.first() {
+        Some(column) => column.clone(),
+        None => return Err(Error::schema(&table.name, "no columns found by describe")),
+    };
 
-    let rows = taos.query("select * from demo.m1").await.unwrap();
+    let mut conditions = options.time_range.conditions(&ts_column, *precision);
+    let mark = lock(marks, "high-water marks")?.get(&table.name);
+    if let Some(ts) = mark {
+        conditions.push(format!("{} > {}", ts_column, ts));
+    }
+    let columns = column_names
+        .iter()
+        .zip(&data_types)
+        .zip(lengths)
+        .map(|((column, data_type), length)| (column.clone(), data_type.to_string(), length))
+        .collect::<Vec<_>>();
+    let (columns_sql, text_columns) = select_list(&table.name, &columns)?;
+    let mut rows = TableRows::new(
+        taos,
+        format!("{}.{}", name, table.name),
+        columns_sql,
+        text_columns,
+        ts_column,
+        conditions,
+        FETCH_BATCH_ROWS,
+    );
 
-    let mut column_names = vec![];
-    for row in rows.column_meta {
-        column_names.push(row.name);
+    // an increment goes to a file of its own, named after the mark it starts from
+    let path = match mark {
+        Some(ts) => incremental_data_file_path(dir_path, &table.name, ts, &options.format),
+        None => data_file_path(dir_path, &table.name, &options.format),
+    };
+    lock(checkpoint, "checkpoint")?.start(&path, &table.name)?;
+    let num_of_points = match options.format {
+        Formats::Parquet => parquet_dumpout(
+            &path,
+            &table.name,
+            &column_names,
+            &data_types,
+            rows.by_ref(),
+            options.row_group_size,
+            *precision,
+        )?,
+        Formats::Avro => avro_dumpout(
+            &path,
+            &table.name,
+            &column_names,
+            &data_types,
+            rows.by_ref(),
+            *precision,
+        )?,
+        Formats::Csv => csv_dumpout(
+            &path,
+            &table.name,
+            &column_names,
+            &data_types,
+            rows.by_ref(),
+            &options.csv,
+            *precision,
+        )?,
+        Formats::Arrow => arrow_dumpout(
+            &path,
+            &table.name,
+            &column_names,
+            &data_types,
+            rows.by_ref(),
+            *precision,
+        )?,
+        Formats::Sql => {
+            let table_ddl = table_ddl(taos, name, &table.name).await?;
+            let preamble = ddl.preamble(name, table.stable.as_deref(), &table_ddl);
+            sql_dumpout(
+                &path,
+                &table.name,
+                &column_names,
+                &data_types,
+                &preamble,
+                rows.by_ref(),
+            )?
+        }
+        Formats::Jsonl => jsonl_dumpout(
+            &path,
+            &table.name,
+            &column_names,
+            &data_types,
+            rows.by_ref(),
+            &options.jsonl,
+            *precision,
+        )?,
+        Formats::LineProtocol => line_protocol_dumpout(
+            &path,
+            table,
+            &column_names,
+            &data_types,
+            rows.by_ref(),
+            *precision,
+        )?,
+    };
+    let summary = rows.summary();
+    if summary.rows == 0 && mark.is_some() {
+        // the table already exists in earlier files, no need for an empty increment
+        fs::remove_file(&path).map_err(|err| Error::io(&path, err))?;
+    } else {
+        let columns = column_names
+            .iter()
+            .cloned()
+            .zip(rows.columns().iter().cloned())
+            .skip(1)
+            .collect();
+        lock(manifest, "manifest")?.add(&path, &table.name, summary, columns)?;
+    }
+    if let (true, Some(ts)) = (options.incremental, rows.last_ts()) {
+        lock(marks, "high-water marks")?.set(&table.name, ts);
+    }
+    lock(checkpoint, "checkpoint")?.finish(&path)?;
+    trace!(
+        "table {} is dumped out with {} points",
+        table.name,
+        num_of_points
+    );
+
+    Ok(num_of_points)
+}
+
+/// Reads a table in timestamp order, one batch of rows per query, so that only a single batch
+/// is held in memory no matter how large the table is. Iteration stops after the first failed
+/// query.
+pub struct TableRows<'a> {
+    taos: &'a Taos,
+    table: String,
+    /// Expressions of the selected columns.
+    columns_sql: String,
+    text_columns: TextColumns,
+    ts_column: String,
+    /// Conditions every row must meet, such as the bounds of the dumped time range.
+    conditions: Vec<String>,
+    batch_size: usize,
+    last_ts: Option<i64>,
+    summary: Summary,
+    /// Aggregates of the rows read so far, by column index.
+    columns: Vec<ColumnAggregate>,
+    done: bool,
+}
+
+impl<'a> TableRows<'a> {
+    pub fn new(
+        taos: &'a Taos,
+        table: String,
+        columns_sql: String,
+        text_columns: TextColumns,
+        ts_column: String,
+        conditions: Vec<String>,
+        batch_size: usize,
+    ) -> Self {
+        TableRows {
+            taos,
+            table,
+            columns_sql,
+            text_columns,
+            ts_column,
+            conditions,
+            batch_size,
+            last_ts: None,
+            summary: Summary::default(),
+            columns: vec![],
+            done: false,
+        }
     }
 
-    assert_eq!(column_names.len(), data_types.len());
+    /// Timestamp of the last row read so far.
+    pub fn last_ts(&self) -> Option<i64> {
+        self.last_ts
+    }
 
-    let path = Path::new(dir_path);
+    /// Number of rows and timestamp range of the rows read so far.
+    pub fn summary(&self) -> Summary {
+        self.summary
+    }
 
-    let file = fs::File::create(&path).unwrap();
+    /// Aggregates of each column of the rows read so far, in column order.
+    pub fn columns(&self) -> &[ColumnAggregate] {
+        &self.columns
+    }
+}
 
-    let num_of_points = match format {
-        Formats::Parquet => parquet_dumpout(file, &column_names, &data_types, rows.rows),
-        Formats::Avro => avro_dumpout(file, &column_names, &data_types, rows.rows),
-    };
+impl Iterator for TableRows<'_> {
+    type Item = Result<Vec<Vec<Field>>>;
 
-    Ok(num_of_points)
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let mut sql = format!("select {} from {}", self.columns_sql, self.table);
+        let mut conditions = self.conditions.clone();
+        // the timestamp is the primary key, so it doubles as the cursor of the next batch
+        if let Some(ts) = self.last_ts {
+            conditions.push(format!("{} > {}", self.ts_column, ts));
+        }
+        if !conditions.is_empty() {
+            sql += &format!(" where {}", conditions.join(" and "));
+        }
+        sql += &format!(" order by {} limit {}", self.ts_column, self.batch_size);
+        let mut rows = match self.taos.raw_query(&sql) {
+            Ok(result) => result.fetch_fields().rows,
+            Err(err) => {
+                self.done = true;
+                return Some(Err(Error::query(sql, err)));
+            }
+        };
+        if rows.len() < self.batch_size {
+            self.done = true;
+        }
+        if rows.is_empty() {
+            return None;
+        }
+        for row in &mut rows {
+            let fetched = check_lengths(&self.table, row, &self.text_columns.varbinary)
+                .and_then(|_| geometry_wkb(&self.table, row, &self.text_columns.geometry));
+            if let Err(err) = fetched {
+                self.done = true;
+                return Some(Err(err));
+            }
+        }
+        for row in &rows {
+            self.summary.add(row[0].as_raw_timestamp());
+            if self.columns.len() < row.len() {
+                self.columns.resize(row.len(), ColumnAggregate::default());
+            }
+            for (column, field) in self.columns.iter_mut().zip(row) {
+                column.add(field);
+            }
+        }
+        self.last_ts = rows.last().and_then(|row| row[0].as_raw_timestamp());
+        Some(Ok(rows))
+    }
 }
 
 #[tokio::main]
-pub async fn dumpout_database_sql(dir_path: &str, name: String) -> Result<()> {
-    let taos = taos_connect().unwrap();
+pub async fn dumpout_database_sql(
+    connect_options: &ConnectOptions,
+    dir_path: &str,
+    name: String,
+) -> Result<()> {
+    let taos = taos_connect(connect_options)?;
+    let sql = format!("show create database {}", name);
     let rows = taos
-        .query(format!("show create database {}", name).as_str())
+        .query(&sql)
         .await
-        .unwrap();
-    let file_name = format!("{}{}.db", dir_path, name);
-    let path = Path::new(file_name.as_str());
-    let mut file = fs::File::create(&path).unwrap();
+        .map_err(|err| Error::query(&sql, err))?;
+    let path = Path::new(dir_path).join(format!("{}.db", name));
+    let mut file = fs::File::create(&path).map_err(|err| Error::io(&path, err))?;
     for row in rows.rows {
         for field in row {
             match field {
@@ -65,44 +735,80 @@ pub async fn dumpout_database_sql(dir_path: &str, name: String) -> Result<()> {
                     if v == name {
                         continue;
                     } else {
-                        file.write(&v).unwrap();
+                        file.write_all(&v).map_err(|err| Error::io(&path, err))?;
                     }
                 }
-                _ => unreachable!(),
+                field => {
+                    return Err(Error::schema(
+                        &name,
+                        format!("unexpected field {} in {}", field, sql),
+                    ))
+                }
             }
         }
     }
     info!(
         "database {} is successfully dumped out to {}",
-        name, file_name
+        name,
+        path.display()
     );
     Ok(())
 }
 
+/// Dump the DDL of every super table of database `name` and return the super table names.
 #[tokio::main]
-pub async fn dumpout_stable_sql(_dir_path: &str, name: String) -> Result<()> {
-    let taos = taos_connect().unwrap();
+pub async fn dumpout_stable_sql(
+    connect_options: &ConnectOptions,
+    dir_path: &str,
+    name: String,
+) -> Result<Vec<String>> {
+    let taos = taos_connect(connect_options)?;
     trace!("taos successfully connected");
-    taos.use_database(&name).await.unwrap();
+    taos.use_database(&name)
+        .await
+        .map_err(|err| Error::query(format!("use {}", name), err))?;
     trace!("use database {}", name);
-    let rows = taos.query("show stables").await.unwrap();
-    if rows.rows.len() == 0 {
+    let rows = taos
+        .query("show stables")
+        .await
+        .map_err(|err| Error::query("show stables", err))?;
+    let mut stables = vec![];
+    if rows.rows.is_empty() {
         info!("no stable found!");
-        return Ok(());
-    } else {
-        trace!("found stables");
+        return Ok(stables);
+    }
+    trace!("found stables");
+    for row in rows.rows {
+        match &row[0] {
+            Field::Binary(v) => stables.push(v.to_string()),
+            _ => continue,
+        }
+    }
+
+    let path = Path::new(dir_path).join(format!("{}.stb", name));
+    let mut file = fs::File::create(&path).map_err(|err| Error::io(&path, err))?;
+    for stable in &stables {
+        let sql = format!("show create stable {}", stable);
+        let rows = taos
+            .query(&sql)
+            .await
+            .map_err(|err| Error::query(&sql, err))?;
         for row in rows.rows {
-            assert!(row.len() > 1);
-            for field in row {
-                println!("{}", field);
-                match field {
-                    Field::Binary(v) => {
-                        println!("{}", v);
-                    }
-                    _ => continue,
+            match row.get(1) {
+                Some(Field::Binary(v)) => {
+                    file.write_all(v)
+                        .and_then(|_| file.write_all(b"\n"))
+                        .map_err(|err| Error::io(&path, err))?;
                 }
+                _ => return Err(Error::schema(stable, format!("no DDL returned by {}", sql))),
             }
         }
     }
-    Ok(())
+    info!(
+        "{} stables of database {} are successfully dumped out to {}",
+        stables.len(),
+        name,
+        path.display()
+    );
+    Ok(stables)
 }
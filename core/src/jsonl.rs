@@ -0,0 +1,354 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+use bstr::BString;
+use libtaos::{Field, Taos, TimestampPrecision};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use utils::error::{Error, Result};
+
+use crate::dumpin::{column_sql_type, create_table_sql, split_length, table_name, InsertBatch};
+use crate::manifest::Summary;
+use crate::time::{convert_epoch, to_rfc3339, TimeBound};
+
+/// How the values of BINARY columns are written to JSON Lines data files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryEncoding {
+    /// Printable ASCII as is, a backslash as `\\` and any other byte as `\xNN`.
+    Escape,
+    /// Standard base64.
+    Base64,
+}
+
+impl FromStr for BinaryEncoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "escape" => Ok(BinaryEncoding::Escape),
+            "base64" => Ok(BinaryEncoding::Base64),
+            _ => Err(format!("{} is not one of escape, base64", s)),
+        }
+    }
+}
+
+/// Layout of JSON Lines data files.
+///
+/// The first line lists the columns and their TDengine types, as in
+/// `{"schema":[{"name":"ts","type":"TIMESTAMP"},{"name":"v","type":"BINARY(20)"}]}`, so that
+/// dumpin can create the table. Every other line is a row, an object with a member per column:
+/// numbers and booleans as such, timestamps as RFC3339 times in UTC and NULL as `null`.
+#[derive(Debug, Clone, Copy)]
+pub struct JsonlOptions {
+    pub binary: BinaryEncoding,
+}
+
+impl Default for JsonlOptions {
+    fn default() -> Self {
+        JsonlOptions {
+            binary: BinaryEncoding::Escape,
+        }
+    }
+}
+
+/// A column of the schema line.
+#[derive(Debug, Serialize, Deserialize)]
+struct Column {
+    name: String,
+    #[serde(rename = "type")]
+    data_type: String,
+}
+
+/// The first line of a JSON Lines data file.
+#[derive(Debug, Serialize, Deserialize)]
+struct SchemaLine {
+    schema: Vec<Column>,
+}
+
+fn escape(bytes: &[u8]) -> String {
+    let mut escaped = String::with_capacity(bytes.len());
+    for &byte in bytes {
+        match byte {
+            b'\\' => escaped.push_str("\\\\"),
+            b' '..=b'~' => escaped.push(byte as char),
+            _ => escaped.push_str(&format!("\\x{:02x}", byte)),
+        }
+    }
+    escaped
+}
+
+fn unescape(escaped: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(escaped.len());
+    let mut rest = escaped.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        rest = tail;
+        if byte != b'\\' {
+            bytes.push(byte);
+            continue;
+        }
+        match rest {
+            [b'\\', tail @ ..] => {
+                bytes.push(b'\\');
+                rest = tail;
+            }
+            [b'x', high, low, tail @ ..] if high.is_ascii_hexdigit() && low.is_ascii_hexdigit() => {
+                let hex = std::str::from_utf8(&[*high, *low]).ok()?.to_string();
+                bytes.push(u8::from_str_radix(&hex, 16).ok()?);
+                rest = tail;
+            }
+            _ => return None,
+        }
+    }
+    Some(bytes)
+}
+
+/// JSON value of a field.
+fn json_value(field: &Field, options: &JsonlOptions, precision: TimestampPrecision) -> Value {
+    match field {
+        Field::Null => Value::Null,
+        Field::Bool(v) => Value::from(*v),
+        Field::TinyInt(v) => Value::from(*v),
+        Field::SmallInt(v) => Value::from(*v),
+        Field::Int(v) => Value::from(*v),
+        Field::BigInt(v) => Value::from(*v),
+        // through the shortest decimal that reads back as the same f32
+        Field::Float(v) => v
+            .to_string()
+            .parse::<f64>()
+            .map(Value::from)
+            .unwrap_or(Value::Null),
+        Field::Double(v) => Value::from(*v),
+        Field::Binary(v) => match options.binary {
+            BinaryEncoding::Escape => Value::from(escape(v)),
+            BinaryEncoding::Base64 => Value::from(base64::encode(v)),
+        },
+        Field::Timestamp(v) => Value::from(to_rfc3339(v, precision)),
+        Field::NChar(v) => Value::from(v.as_str()),
+        Field::UTinyInt(v) => Value::from(*v),
+        Field::USmallInt(v) => Value::from(*v),
+        Field::UInt(v) => Value::from(*v),
+        Field::UBigInt(v) => Value::from(*v),
+    }
+}
+
+/// Write the rows of `table` to a JSON Lines file at `path` and return the number of values
+/// written.
+pub fn jsonl_dumpout(
+    path: &Path,
+    table: &str,
+    column_names: &[String],
+    data_types: &[BString],
+    rows: impl Iterator<Item = Result<Vec<Vec<Field>>>>,
+    options: &JsonlOptions,
+    precision: TimestampPrecision,
+) -> Result<i64> {
+    let mut schema = vec![];
+    for (name, data_type) in column_names.iter().zip(data_types) {
+        let data_type = data_type.to_string();
+        column_sql_type(table, name, &data_type)?;
+        schema.push(Column {
+            name: name.clone(),
+            data_type,
+        });
+    }
+
+    let file = File::create(path).map_err(|err| Error::io(path, err))?;
+    let mut writer = BufWriter::new(file);
+    serde_json::to_writer(&mut writer, &SchemaLine { schema })
+        .map_err(|err| Error::encode(path, err))?;
+    writer
+        .write_all(b"\n")
+        .map_err(|err| Error::io(path, err))?;
+    let mut data_point = 0;
+    for rows in rows {
+        for row in rows? {
+            let mut object = Map::new();
+            for (name, field) in column_names.iter().zip(&row) {
+                object.insert(name.clone(), json_value(field, options, precision));
+                data_point += 1;
+            }
+            serde_json::to_writer(&mut writer, &object).map_err(|err| Error::encode(path, err))?;
+            writer
+                .write_all(b"\n")
+                .map_err(|err| Error::io(path, err))?;
+        }
+    }
+    writer.flush().map_err(|err| Error::io(path, err))?;
+    Ok(data_point)
+}
+
+/// The lines of `file` but blank ones.
+fn read_lines(file: &Path) -> Result<impl Iterator<Item = Result<String>>> {
+    let f = File::open(file).map_err(|err| Error::io(file, err))?;
+    let path = file.to_path_buf();
+    Ok(BufReader::new(f)
+        .lines()
+        .map(move |line| line.map_err(|err| Error::io(&path, err)))
+        .filter(|line| !matches!(line, Ok(line) if line.trim().is_empty())))
+}
+
+/// Names and types of the columns listed by the schema line.
+fn read_schema(
+    table: &str,
+    lines: &mut impl Iterator<Item = Result<String>>,
+) -> Result<Vec<(String, String)>> {
+    let line = match lines.next() {
+        Some(line) => line?,
+        None => return Err(Error::schema(table, "JSON Lines file has no schema line")),
+    };
+    let schema: SchemaLine = serde_json::from_str(&line)
+        .map_err(|err| Error::schema(table, format!("invalid JSON Lines schema line: {}", err)))?;
+    Ok(schema
+        .schema
+        .into_iter()
+        .map(|column| (column.name, column.data_type))
+        .collect())
+}
+
+/// Value of a member as dumped out of a column of type `data_type`, `None` when it is invalid,
+/// with timestamps converted from `source` to `precision`.
+fn parse_value(
+    value: &Value,
+    data_type: &str,
+    options: &JsonlOptions,
+    source: TimestampPrecision,
+    precision: TimestampPrecision,
+) -> Option<Field> {
+    if value.is_null() {
+        return Some(Field::Null);
+    }
+    Some(match split_length(data_type).0 {
+        "TIMESTAMP" => match value {
+            Value::String(v) => Field::BigInt(
+                v.parse::<TimeBound>()
+                    .ok()?
+                    .to_epoch_from(source, precision),
+            ),
+            value => Field::BigInt(convert_epoch(value.as_i64()?, source, precision)),
+        },
+        "BOOL" => Field::Bool(value.as_bool()?),
+        "TINYINT" => Field::TinyInt(value.as_i64()?.try_into().ok()?),
+        "SMALLINT" => Field::SmallInt(value.as_i64()?.try_into().ok()?),
+        "INT" => Field::Int(value.as_i64()?.try_into().ok()?),
+        "BIGINT" => Field::BigInt(value.as_i64()?),
+        "FLOAT" => Field::Float(value.as_f64()? as f32),
+        "DOUBLE" => Field::Double(value.as_f64()?),
+        "TINYINT UNSIGNED" => Field::UTinyInt(value.as_u64()?.try_into().ok()?),
+        "SMALLINT UNSIGNED" => Field::USmallInt(value.as_u64()?.try_into().ok()?),
+        "INT UNSIGNED" => Field::UInt(value.as_u64()?.try_into().ok()?),
+        "BIGINT UNSIGNED" => Field::UBigInt(value.as_u64()?),
+        "BINARY" | "VARCHAR" | "VARBINARY" | "GEOMETRY" | "JSON" => {
+            let value = value.as_str()?;
+            let bytes = match options.binary {
+                BinaryEncoding::Escape => unescape(value)?,
+                BinaryEncoding::Base64 => base64::decode(value).ok()?,
+            };
+            Field::Binary(BString::from(bytes))
+        }
+        "NCHAR" => Field::NChar(value.as_str()?.to_string()),
+        _ => return None,
+    })
+}
+
+/// Count the rows of a JSON Lines data file and find the range of its timestamp column.
+pub fn jsonl_summary(file: &Path, precision: TimestampPrecision) -> Result<Summary> {
+    let table = table_name(file)?;
+    let mut lines = read_lines(file)?;
+    let columns = read_schema(table, &mut lines)?;
+    let ts_column = columns.first().map(|(name, _)| name.as_str());
+    let mut summary = Summary::default();
+    for line in lines {
+        let row: Map<String, Value> =
+            serde_json::from_str(&line?).map_err(|err| Error::decode(file, err))?;
+        let ts = ts_column
+            .and_then(|name| row.get(name))
+            .and_then(|value| match value {
+                Value::String(v) => v.parse::<TimeBound>().ok().map(|ts| ts.to_epoch(precision)),
+                value => value.as_i64(),
+            });
+        summary.add(ts);
+    }
+    Ok(summary)
+}
+
+/// Load a JSON Lines data file into a database of `precision`, where epochs are in `source`,
+/// the precision of the dumped database.
+pub async fn jsonl_dumpin(
+    taos: &Taos,
+    file: &Path,
+    batch_rows: usize,
+    options: &JsonlOptions,
+    source: TimestampPrecision,
+    precision: TimestampPrecision,
+) -> Result<()> {
+    let table = table_name(file)?;
+    let mut lines = read_lines(file)?;
+    let columns = read_schema(table, &mut lines)?;
+    let sql = create_table_sql(table, &columns)?;
+    taos.query(&sql)
+        .await
+        .map_err(|err| Error::query(&sql, err))?;
+
+    let mut batch = InsertBatch::new(taos, table, file, batch_rows, precision).await?;
+    for (line_number, line) in lines.enumerate() {
+        let row: Map<String, Value> =
+            serde_json::from_str(&line?).map_err(|err| Error::decode(file, err))?;
+        let mut fields = Vec::with_capacity(columns.len());
+        for (name, data_type) in &columns {
+            // a missing member is NULL
+            let value = row.get(name).unwrap_or(&Value::Null);
+            let field =
+                parse_value(value, data_type, options, source, precision).ok_or_else(|| {
+                    Error::decode(
+                        file,
+                        format!(
+                            "invalid {} value {} of column {} in row {}",
+                            data_type,
+                            value,
+                            name,
+                            line_number + 1
+                        ),
+                    )
+                })?;
+            fields.push(field);
+        }
+        batch.push(fields).await?;
+    }
+    batch.flush().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escape_keeps_printable_ascii() {
+        assert_eq!(escape(b"plain text ~"), "plain text ~");
+        assert_eq!(escape(b"back\\slash"), "back\\\\slash");
+        assert_eq!(escape(b"\x00\n\x7f\xff"), "\\x00\\x0a\\x7f\\xff");
+        assert_eq!(escape("é".as_bytes()), "\\xc3\\xa9");
+    }
+
+    #[test]
+    fn every_byte_round_trips() {
+        let bytes = (0..=255).collect::<Vec<u8>>();
+        assert_eq!(unescape(&escape(&bytes)), Some(bytes));
+        assert_eq!(unescape(""), Some(vec![]));
+    }
+
+    #[test]
+    fn unescape_accepts_either_case() {
+        assert_eq!(unescape("\\xAb\\xaB"), Some(vec![0xab, 0xab]));
+    }
+
+    #[test]
+    fn invalid_escapes() {
+        for escaped in [
+            "\\", "a\\", "\\q", "\\x", "\\x1", "\\xg0", "\\x+f", "\\x-1", "\\xé",
+        ] {
+            assert_eq!(unescape(escaped), None, "{}", escaped);
+        }
+    }
+}
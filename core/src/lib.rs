@@ -1,8 +1,18 @@
+pub mod arrow;
 pub mod avro;
 pub mod commands;
+pub mod csv;
 pub mod dumpin;
 pub mod dumpout;
+pub mod jsonl;
+pub mod line_protocol;
+pub mod manifest;
 pub mod parquet;
+pub mod sql;
+pub mod state;
+pub mod time;
+pub mod validate;
+pub mod wkb;
 
 use utils::error::Result;
 
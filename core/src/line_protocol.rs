@@ -0,0 +1,464 @@
+use std::ffi::CString;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Lines, Write};
+use std::os::raw::{c_char, c_int};
+use std::path::Path;
+
+use bstr::BString;
+use libtaos::bindings::{TAOS, TAOS_RES};
+use libtaos::{CTaosResult, Field, Taos, TimestampPrecision};
+use log::warn;
+use utils::error::{Error, Result};
+
+use crate::dumpin::{is_bytes_type, table_name};
+use crate::dumpout::Table;
+use crate::manifest::Summary;
+use crate::time::convert_epoch;
+
+/// `TSDB_SML_LINE_PROTOCOL` of `taos.h`.
+const LINE_PROTOCOL: c_int = 1;
+/// `TSDB_SML_TIMESTAMP_NANO_SECONDS` of `taos.h`.
+const NANO_SECONDS: c_int = 6;
+
+extern "C" {
+    /// Schemaless insert of TDengine 2.4 and later, which the bindings of libtaos lack.
+    fn taos_schemaless_insert(
+        taos: *mut TAOS,
+        lines: *mut *mut c_char,
+        num_lines: c_int,
+        protocol: c_int,
+        precision: c_int,
+    ) -> *mut TAOS_RES;
+}
+
+/// Tag naming the child table of a record, read by schemaless insert when the client sets
+/// `smlChildTableName tname` in `taos.cfg`.
+pub const CHILD_TABLE_TAG: &str = "tname";
+
+/// Characters escaped with a backslash in measurements.
+const MEASUREMENT_SPECIAL: &[char] = &[',', ' '];
+/// Characters escaped with a backslash in tag keys, tag values and field keys.
+const KEY_SPECIAL: &[char] = &[',', '=', ' '];
+/// Characters escaped with a backslash in string field values.
+const STRING_SPECIAL: &[char] = &['"', '\\'];
+
+fn escape(value: &str, special: &[char]) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if special.contains(&c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Text of a BINARY or VARCHAR value of column or tag `name`. Line protocol has no way to
+/// write arbitrary bytes, so VARBINARY, GEOMETRY and invalid UTF-8 values are refused.
+fn text<'a>(path: &Path, name: &str, value: &'a BString, data_type: &str) -> Result<&'a str> {
+    if is_bytes_type(data_type) {
+        return Err(Error::encode(
+            path,
+            format!("{} value of {} has no line protocol form", data_type, name),
+        ));
+    }
+    std::str::from_utf8(value)
+        .map_err(|err| Error::encode(path, format!("value of {} is not UTF-8: {}", name, err)))
+}
+
+/// Text of tag `name`, `None` for NULL and empty values, which line protocol leaves out.
+fn tag_value(path: &Path, name: &str, field: &Field, data_type: &str) -> Result<Option<String>> {
+    let value = match field {
+        Field::Null => return Ok(None),
+        Field::Binary(v) => text(path, name, v, data_type)?.to_string(),
+        Field::NChar(v) => v.clone(),
+        Field::Timestamp(v) => v.as_raw_timestamp().to_string(),
+        field => field.to_string(),
+    };
+    if value.contains(['\n', '\r']) {
+        return Err(Error::encode(
+            path,
+            format!("value of tag {} holds a line break", name),
+        ));
+    }
+    match value.is_empty() {
+        true => Ok(None),
+        false => Ok(Some(value)),
+    }
+}
+
+/// Field value of column `name`, with the suffix or quotes that schemaless insert maps back to
+/// its type, `None` for NULL. NaN and infinite floats have no line protocol form and are
+/// refused.
+fn field_value(path: &Path, name: &str, field: &Field, data_type: &str) -> Result<Option<String>> {
+    Ok(Some(match field {
+        Field::Null => return Ok(None),
+        Field::Bool(v) => v.to_string(),
+        Field::TinyInt(v) => format!("{}i8", v),
+        Field::SmallInt(v) => format!("{}i16", v),
+        Field::Int(v) => format!("{}i32", v),
+        Field::BigInt(v) => format!("{}i64", v),
+        Field::Float(v) if v.is_finite() => format!("{}f32", v),
+        Field::Double(v) if v.is_finite() => format!("{}f64", v),
+        Field::Float(_) | Field::Double(_) => {
+            return Err(Error::encode(
+                path,
+                format!("value {} of {} has no line protocol form", field, name),
+            ))
+        }
+        Field::Binary(v) => format!(
+            "\"{}\"",
+            escape(text(path, name, v, data_type)?, STRING_SPECIAL)
+        ),
+        Field::NChar(v) => format!("L\"{}\"", escape(v, STRING_SPECIAL)),
+        Field::Timestamp(v) => format!("{}i64", v.as_raw_timestamp()),
+        Field::UTinyInt(v) => format!("{}u8", v),
+        Field::USmallInt(v) => format!("{}u16", v),
+        Field::UInt(v) => format!("{}u32", v),
+        Field::UBigInt(v) => format!("{}u64", v),
+    }))
+}
+
+/// Write the rows of child table `table` to a line protocol file at `path` and return the
+/// number of values written.
+///
+/// Every row is a `stable,tname=table,tag=v field=v ts` line, with the tag values of the table.
+/// Values carry the suffixes of TDengine schemaless insert, such as `i8` for TINYINT, `u32` for
+/// INT UNSIGNED, `f32` for FLOAT and `L"..."` for NCHAR, NULL values are left out and timestamps
+/// are in nanoseconds, the default precision of InfluxDB. VARBINARY and GEOMETRY values, text that
+/// is not UTF-8 and NaN or infinite floats cannot be written and fail the table.
+///
+/// Like every other format, each child table goes to a file of its own rather than one file per
+/// super table, so that tables are dumped in parallel, checkpointed and dumped incrementally
+/// one by one. The lines of all the files of a super table share its measurement, and loading
+/// them together restores it.
+///
+/// Schemaless insert restores less than the other formats: tags come back as NCHAR, timestamp
+/// columns other than the first as BIGINT, child tables keep their name only when the client
+/// sets `smlChildTableName tname`, and a row whose values are all NULL cannot be written and is
+/// skipped. Normal tables would come back as super tables, and are not dumped to this format.
+pub fn line_protocol_dumpout(
+    path: &Path,
+    table: &Table,
+    column_names: &[String],
+    data_types: &[BString],
+    rows: impl Iterator<Item = Result<Vec<Vec<Field>>>>,
+    precision: TimestampPrecision,
+) -> Result<i64> {
+    let mut series = format!(
+        "{},{}={}",
+        escape(
+            table.stable.as_deref().unwrap_or(&table.name),
+            MEASUREMENT_SPECIAL
+        ),
+        CHILD_TABLE_TAG,
+        escape(&table.name, KEY_SPECIAL)
+    );
+    for ((name, value), data_type) in table.tags.iter().zip(&table.tag_types) {
+        if let Some(value) = tag_value(path, name, value, data_type)? {
+            series += &format!(
+                ",{}={}",
+                escape(name, KEY_SPECIAL),
+                escape(&value, KEY_SPECIAL)
+            );
+        }
+    }
+    let data_types = data_types
+        .iter()
+        .map(|data_type| data_type.to_string())
+        .collect::<Vec<_>>();
+    let field_keys = column_names
+        .iter()
+        .map(|name| escape(name, KEY_SPECIAL))
+        .collect::<Vec<_>>();
+
+    let file = File::create(path).map_err(|err| Error::io(path, err))?;
+    let mut writer = BufWriter::new(file);
+    let mut data_point = 0;
+    let mut skipped = 0;
+    for rows in rows {
+        for row in rows? {
+            let ts = match row.first().and_then(Field::as_raw_timestamp) {
+                Some(ts) => convert_epoch(ts, precision, TimestampPrecision::Nano),
+                None => return Err(Error::encode(path, "row has no timestamp")),
+            };
+            let mut fields = vec![];
+            for (index, field) in row.iter().enumerate().skip(1) {
+                let (name, data_type) = (&column_names[index], &data_types[index]);
+                if let Some(value) = field_value(path, name, field, data_type)? {
+                    fields.push(format!("{}={}", field_keys[index], value));
+                }
+            }
+            if fields.is_empty() {
+                skipped += 1;
+                continue;
+            }
+            data_point += row.len() as i64;
+            writeln!(writer, "{} {} {}", series, fields.join(","), ts)
+                .map_err(|err| Error::io(path, err))?;
+        }
+    }
+    writer.flush().map_err(|err| Error::io(path, err))?;
+    if skipped > 0 {
+        warn!(
+            "{} rows with NULL values only are left out of {}",
+            skipped,
+            path.display()
+        );
+    }
+    Ok(data_point)
+}
+
+/// Records of a line protocol file, a string field value going on over line breaks, and
+/// comment lines skipped.
+struct Records<R> {
+    lines: Lines<R>,
+}
+
+impl<R: BufRead> Iterator for Records<R> {
+    type Item = io::Result<String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut record = String::new();
+        for line in self.lines.by_ref() {
+            let line = match line {
+                Ok(line) => line,
+                Err(err) => return Some(Err(err)),
+            };
+            if record.is_empty() {
+                let trimmed = line.trim();
+                if trimmed.is_empty() || trimmed.starts_with('#') {
+                    continue;
+                }
+            } else {
+                record.push('\n');
+            }
+            record += &line;
+            if !in_string(&record) {
+                return Some(Ok(record));
+            }
+        }
+        match record.is_empty() {
+            true => None,
+            false => Some(Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "string field value is not terminated",
+            ))),
+        }
+    }
+}
+
+/// Whether `record` ends within a string field value.
+fn in_string(record: &str) -> bool {
+    // quotes only delimit strings past the measurement and tags
+    let mut in_fields = false;
+    let mut in_quote = false;
+    let mut escaped = false;
+    for c in record.chars() {
+        if escaped {
+            escaped = false;
+        } else if c == '\\' {
+            escaped = true;
+        } else if in_quote {
+            in_quote = c != '"';
+        } else if c == ' ' {
+            in_fields = true;
+        } else if c == '"' && in_fields {
+            in_quote = true;
+        }
+    }
+    in_quote
+}
+
+fn read_records(file: &Path) -> Result<Records<BufReader<File>>> {
+    let f = File::open(file).map_err(|err| Error::io(file, err))?;
+    Ok(Records {
+        lines: BufReader::new(f).lines(),
+    })
+}
+
+/// Count the records of a line protocol data file and find the range of their timestamps, in
+/// `precision`.
+pub fn line_protocol_summary(file: &Path, precision: TimestampPrecision) -> Result<Summary> {
+    let mut summary = Summary::default();
+    for record in read_records(file)? {
+        let record = record.map_err(|err| Error::io(file, err))?;
+        let ts = record
+            .rsplit_once(' ')
+            .and_then(|(_, ts)| ts.trim().parse().ok())
+            .map(|ts| convert_epoch(ts, TimestampPrecision::Nano, precision));
+        summary.add(ts);
+    }
+    Ok(summary)
+}
+
+/// Load a line protocol data file through schemaless insert, `batch_rows` records at a time.
+/// TDengine creates the super tables, tables and columns the records need, naming the child
+/// tables after their `tname` tag when the client sets `smlChildTableName tname`, or else after
+/// a hash of their tags.
+pub async fn line_protocol_dumpin(taos: &Taos, file: &Path, batch_rows: usize) -> Result<()> {
+    let table = table_name(file)?;
+    let mut records = vec![];
+    for record in read_records(file)? {
+        records.push(record.map_err(|err| Error::io(file, err))?);
+        if records.len() >= batch_rows.max(1) {
+            schemaless_insert(taos, table, file, &records)?;
+            records.clear();
+        }
+    }
+    if !records.is_empty() {
+        schemaless_insert(taos, table, file, &records)?;
+    }
+    Ok(())
+}
+
+fn schemaless_insert(taos: &Taos, table: &str, file: &Path, records: &[String]) -> Result<()> {
+    let mut lines = records
+        .iter()
+        .map(|record| {
+            CString::new(record.as_str())
+                .map(CString::into_bytes_with_nul)
+                .map_err(|err| Error::decode(file, err))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let mut pointers = lines
+        .iter_mut()
+        .map(|line| line.as_mut_ptr() as *mut c_char)
+        .collect::<Vec<_>>();
+    let res = unsafe {
+        taos_schemaless_insert(
+            taos.as_raw(),
+            pointers.as_mut_ptr(),
+            pointers.len() as c_int,
+            LINE_PROTOCOL,
+            NANO_SECONDS,
+        )
+    };
+    match CTaosResult::new(res) {
+        Ok(_) => Ok(()),
+        Err(source) => Err(Error::InsertRejected {
+            table: table.to_string(),
+            file: file.to_path_buf(),
+            sql: records.join("\n"),
+            source: source.into(),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use libtaos::Timestamp;
+
+    use super::*;
+
+    fn records(content: &str) -> io::Result<Vec<String>> {
+        Records {
+            lines: Cursor::new(content).lines(),
+        }
+        .collect()
+    }
+
+    #[test]
+    fn escaped_names() {
+        assert_eq!(
+            escape("cpu load,a=b", MEASUREMENT_SPECIAL),
+            "cpu\\ load\\,a=b"
+        );
+        assert_eq!(escape("host name,a=b", KEY_SPECIAL), "host\\ name\\,a\\=b");
+    }
+
+    #[test]
+    fn escaped_string_fields() {
+        let path = Path::new("t1.lp");
+        let value = Field::Binary("say \"hi\" \\ bye".into());
+        assert_eq!(
+            field_value(path, "c", &value, "VARCHAR").unwrap().unwrap(),
+            "\"say \\\"hi\\\" \\\\ bye\""
+        );
+        let value = Field::NChar("a \"b\"".into());
+        assert_eq!(
+            field_value(path, "c", &value, "NCHAR").unwrap().unwrap(),
+            "L\"a \\\"b\\\"\""
+        );
+    }
+
+    #[test]
+    fn values_without_line_protocol_form() {
+        let path = Path::new("t1.lp");
+        let bytes = Field::Binary(vec![0xff, 0xfe].into());
+        assert!(field_value(path, "c", &bytes, "BINARY").is_err());
+        assert!(tag_value(path, "t", &bytes, "BINARY").is_err());
+        let ascii = Field::Binary("ab".into());
+        assert!(field_value(path, "c", &ascii, "VARBINARY").is_err());
+        assert!(tag_value(path, "t", &ascii, "VARBINARY").is_err());
+        assert!(field_value(path, "c", &Field::Float(f32::NAN), "FLOAT").is_err());
+        assert!(field_value(path, "c", &Field::Double(f64::INFINITY), "DOUBLE").is_err());
+        assert!(tag_value(path, "t", &Field::NChar("a\nb".into()), "NCHAR").is_err());
+        assert_eq!(
+            tag_value(path, "t", &Field::NChar("".into()), "NCHAR").unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn escaped_lines() {
+        let path = std::env::temp_dir().join(format!("lp-{}.lp", std::process::id()));
+        let table = Table {
+            name: "d 1".into(),
+            stable: Some("cpu,load".into()),
+            tags: vec![("host=name".into(), Field::Binary("a b".into()))],
+            tag_types: vec!["VARCHAR".into()],
+        };
+        let column_names = ["ts", "user load", "note"].map(String::from);
+        let data_types = ["TIMESTAMP", "INT", "VARCHAR"].map(BString::from);
+        let rows = vec![
+            vec![
+                Field::Timestamp(Timestamp::new(1, TimestampPrecision::Milli)),
+                Field::Int(2),
+                Field::Binary("x=\"y\"".into()),
+            ],
+            vec![
+                Field::Timestamp(Timestamp::new(2, TimestampPrecision::Milli)),
+                Field::Null,
+                Field::Null,
+            ],
+        ];
+        let points = line_protocol_dumpout(
+            &path,
+            &table,
+            &column_names,
+            &data_types,
+            vec![Ok(rows)].into_iter(),
+            TimestampPrecision::Milli,
+        )
+        .unwrap();
+        let content = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(points, 3);
+        assert_eq!(
+            content,
+            "cpu\\,load,tname=d\\ 1,host\\=name=a\\ b user\\ load=2i32,note=\"x=\\\"y\\\"\" 1000000\n"
+        );
+    }
+
+    #[test]
+    fn quotes_delimit_strings_in_fields_only() {
+        assert!(!in_string("m,t=a\"b f=1i32 1"));
+        assert!(!in_string("m f=\"a b\" 1"));
+        assert!(in_string("m f=\"a b"));
+        assert!(in_string("m f=\"a\\\""));
+        assert!(!in_string("m f=\"a\\\\\" 1"));
+    }
+
+    #[test]
+    fn records_over_line_breaks() {
+        let content = "# comment\n\nm f=\"a\n# b\" 1\nm f=1i32 2\n";
+        assert_eq!(
+            records(content).unwrap(),
+            vec!["m f=\"a\n# b\" 1".to_string(), "m f=1i32 2".to_string()]
+        );
+        assert!(records("m f=\"a\n").is_err());
+    }
+}
@@ -0,0 +1,307 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
+
+use libtaos::{Field, TimestampPrecision};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use utils::error::{Error, Result};
+use utils::{get_all_data_files, Formats};
+
+use crate::arrow::arrow_summary;
+use crate::avro::avro_summary;
+use crate::csv::{csv_summary, CsvOptions};
+use crate::jsonl::jsonl_summary;
+use crate::line_protocol::line_protocol_summary;
+use crate::parquet::parquet_summary;
+use crate::sql::sql_summary;
+use crate::state::{file_name, read_journal, read_json, write_json, Journal};
+
+/// Number of rows and timestamp range of a data file.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Summary {
+    pub rows: u64,
+    pub min_ts: Option<i64>,
+    pub max_ts: Option<i64>,
+}
+
+impl Summary {
+    /// Account for a row with timestamp `ts`.
+    pub fn add(&mut self, ts: Option<i64>) {
+        self.rows += 1;
+        if let Some(ts) = ts {
+            self.min_ts = Some(self.min_ts.map_or(ts, |min| min.min(ts)));
+            self.max_ts = Some(self.max_ts.map_or(ts, |max| max.max(ts)));
+        }
+    }
+
+    /// Account for the rows of another file of the same table.
+    pub fn merge(&mut self, other: &Summary) {
+        self.rows += other.rows;
+        for ts in [other.min_ts, other.max_ts].into_iter().flatten() {
+            self.min_ts = Some(self.min_ts.map_or(ts, |min| min.min(ts)));
+            self.max_ts = Some(self.max_ts.map_or(ts, |max| max.max(ts)));
+        }
+    }
+}
+
+/// Number of values and, for a numeric column, their sum, as `count(<column>)` and
+/// `sum(<column>)` return them.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ColumnAggregate {
+    pub count: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sum: Option<f64>,
+}
+
+impl ColumnAggregate {
+    /// Account for a value of the column, NULL values are not counted.
+    pub fn add(&mut self, field: &Field) {
+        if *field == Field::Null {
+            return;
+        }
+        self.count += 1;
+        if let Some(value) = numeric_value(field) {
+            *self.sum.get_or_insert(0.0) += value;
+        }
+    }
+
+    /// Account for the values of another file of the same table.
+    pub fn merge(&mut self, other: &ColumnAggregate) {
+        self.count += other.count;
+        if let Some(sum) = other.sum {
+            *self.sum.get_or_insert(0.0) += sum;
+        }
+    }
+}
+
+/// Value of a numeric field, the only ones TDengine sums up.
+pub fn numeric_value(field: &Field) -> Option<f64> {
+    match field {
+        Field::TinyInt(v) => Some(*v as f64),
+        Field::SmallInt(v) => Some(*v as f64),
+        Field::Int(v) => Some(*v as f64),
+        Field::BigInt(v) => Some(*v as f64),
+        Field::Float(v) => Some(*v as f64),
+        Field::Double(v) => Some(*v),
+        Field::UTinyInt(v) => Some(*v as f64),
+        Field::USmallInt(v) => Some(*v as f64),
+        Field::UInt(v) => Some(*v as f64),
+        Field::UBigInt(v) => Some(*v as f64),
+        _ => None,
+    }
+}
+
+/// What was written to a data file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FileEntry {
+    pub table: String,
+    #[serde(flatten)]
+    pub summary: Summary,
+    pub bytes: u64,
+    pub sha256: String,
+    /// Aggregates by column, the timestamp column excepted.
+    #[serde(default)]
+    pub columns: BTreeMap<String, ColumnAggregate>,
+}
+
+/// A data file as recorded in the manifest journal.
+#[derive(Debug, Serialize, Deserialize)]
+struct FileRecord {
+    file: String,
+    #[serde(flatten)]
+    entry: FileEntry,
+}
+
+/// Every data file of a dump directory, kept in `<database>.manifest.json`. Each file is
+/// appended to `<database>.manifest.journal` once written, and the journal is folded into the
+/// manifest when it is saved.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Manifest {
+    #[serde(skip)]
+    path: PathBuf,
+    #[serde(skip)]
+    journal: Option<Journal>,
+    /// Timestamp precision of the database, to read timestamps written as text.
+    #[serde(default)]
+    pub precision: Option<TimestampPrecision>,
+    /// Entry by data file name.
+    pub files: BTreeMap<String, FileEntry>,
+}
+
+impl Manifest {
+    pub fn path(dir_path: &str, name: &str) -> PathBuf {
+        Path::new(dir_path).join(format!("{}.manifest.json", name))
+    }
+
+    fn journal_path(dir_path: &str, name: &str) -> PathBuf {
+        Path::new(dir_path).join(format!("{}.manifest.journal", name))
+    }
+
+    /// The saved manifest of database `name` with the files journaled since, `None` when
+    /// there is neither.
+    pub fn read(dir_path: &str, name: &str) -> Result<Option<Self>> {
+        let manifest: Option<Manifest> = read_json(&Self::path(dir_path, name))?;
+        let (records, _) = read_journal::<FileRecord>(&Self::journal_path(dir_path, name))?;
+        if manifest.is_none() && records.is_empty() {
+            return Ok(None);
+        }
+        let mut manifest = manifest.unwrap_or_default();
+        for record in records {
+            manifest.files.insert(record.file, record.entry);
+        }
+        Ok(Some(manifest))
+    }
+
+    /// Load the manifest of database `name`, failing when there is none.
+    pub fn load(dir_path: &str, name: &str) -> Result<Self> {
+        let path = Self::path(dir_path, name);
+        let mut manifest = Self::read(dir_path, name)?.ok_or_else(|| {
+            Error::io(
+                &path,
+                io::Error::new(io::ErrorKind::NotFound, "no manifest in dump directory"),
+            )
+        })?;
+        manifest.path = path;
+        Ok(manifest)
+    }
+
+    /// Keep the entries of an earlier dump, for dumps adding files to it, or start afresh.
+    pub fn open(dir_path: &str, name: &str, keep: bool) -> Result<Self> {
+        let mut manifest = match keep {
+            true => Self::read(dir_path, name)?.unwrap_or_default(),
+            false => Manifest::default(),
+        };
+        let (journal, _) = Journal::open::<FileRecord>(Self::journal_path(dir_path, name), keep)?;
+        manifest.path = Self::path(dir_path, name);
+        manifest.journal = Some(journal);
+        Ok(manifest)
+    }
+
+    /// Write the manifest with every file recorded so far, and empty the journal.
+    pub fn save(&mut self) -> Result<()> {
+        write_json(&self.path, self)?;
+        match &mut self.journal {
+            Some(journal) => journal.clear(),
+            None => Ok(()),
+        }
+    }
+
+    /// Record the data file `file` of `table` once it is completely written.
+    pub fn add(
+        &mut self,
+        file: &Path,
+        table: &str,
+        summary: Summary,
+        columns: BTreeMap<String, ColumnAggregate>,
+    ) -> Result<()> {
+        let (bytes, sha256) = digest(file)?;
+        let record = FileRecord {
+            file: file_name(file),
+            entry: FileEntry {
+                table: table.to_string(),
+                summary,
+                bytes,
+                sha256,
+                columns,
+            },
+        };
+        if let Some(journal) = &mut self.journal {
+            journal.append(&record)?;
+        }
+        self.files.insert(record.file, record.entry);
+        Ok(())
+    }
+}
+
+/// Size and hex SHA-256 of a file.
+fn digest(file: &Path) -> Result<(u64, String)> {
+    let mut reader = BufReader::new(File::open(file).map_err(|err| Error::io(file, err))?);
+    let mut hasher = Sha256::new();
+    let bytes = io::copy(&mut reader, &mut hasher).map_err(|err| Error::io(file, err))?;
+    Ok((bytes, format!("{:x}", hasher.finalize())))
+}
+
+/// Check every data file of the dump of database `name` against its manifest, without connecting
+/// to TDengine, and report each file that does not match.
+pub fn verify(dir_path: &str, name: &str, format: &Formats, csv: &CsvOptions) -> Result<()> {
+    let manifest = Manifest::load(dir_path, name)?;
+    let mut failures = 0;
+    let extension = format!(".{}", format.extension());
+    for (file_name, entry) in &manifest.files {
+        if !file_name.ends_with(&extension) {
+            continue;
+        }
+        let file = Path::new(dir_path).join(file_name);
+        let precision = manifest.precision.unwrap_or(TimestampPrecision::Milli);
+        match verify_file(&file, entry, format, csv, precision) {
+            Ok(problems) if problems.is_empty() => println!("{}: ok", file_name),
+            Ok(problems) => {
+                failures += 1;
+                println!("{}: {}", file_name, problems.join(", "));
+            }
+            Err(err) => {
+                failures += 1;
+                println!("{}: {}", file_name, err);
+            }
+        }
+    }
+
+    // files the manifest does not know about were not written by the dump
+    for file in get_all_data_files(dir_path, format)? {
+        let file_name = file_name(&file);
+        if !manifest.files.contains_key(&file_name) {
+            failures += 1;
+            println!("{}: not in manifest", file_name);
+        }
+    }
+
+    if failures > 0 {
+        return Err(Error::Verify(format!(
+            "{} of the files in {} do not match the manifest",
+            failures, dir_path
+        )));
+    }
+    Ok(())
+}
+
+/// Differences between a data file and its manifest entry.
+fn verify_file(
+    file: &Path,
+    entry: &FileEntry,
+    format: &Formats,
+    csv: &CsvOptions,
+    precision: TimestampPrecision,
+) -> Result<Vec<String>> {
+    let mut problems = vec![];
+    let (bytes, sha256) = digest(file)?;
+    if bytes != entry.bytes {
+        problems.push(format!("{} bytes, expected {}", bytes, entry.bytes));
+    }
+    if sha256 != entry.sha256 {
+        problems.push("SHA-256 mismatch".to_string());
+    }
+    let summary = match format {
+        Formats::Parquet => parquet_summary(file)?,
+        Formats::Avro => avro_summary(file)?,
+        Formats::Csv => csv_summary(file, csv, precision)?,
+        Formats::Jsonl => jsonl_summary(file, precision)?,
+        Formats::Arrow => arrow_summary(file, precision)?,
+        Formats::Sql => sql_summary(file)?,
+        Formats::LineProtocol => line_protocol_summary(file, precision)?,
+    };
+    if summary.rows != entry.summary.rows {
+        problems.push(format!(
+            "{} rows, expected {}",
+            summary.rows, entry.summary.rows
+        ));
+    }
+    if (summary.min_ts, summary.max_ts) != (entry.summary.min_ts, entry.summary.max_ts) {
+        problems.push(format!(
+            "timestamps {:?}..{:?}, expected {:?}..{:?}",
+            summary.min_ts, summary.max_ts, entry.summary.min_ts, entry.summary.max_ts
+        ));
+    }
+    Ok(problems)
+}
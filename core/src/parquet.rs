@@ -1,326 +1,595 @@
 use bstr::BString;
 use libtaos::*;
+use log::trace;
 use parquet::basic::{
     ConvertedType, LogicalType, Repetition, TimeUnit, TimestampType, Type as PhysicalType,
 };
+use parquet::data_type::ByteArray;
+use parquet::errors::ParquetError;
 use parquet::file::reader::{FileReader, SerializedFileReader};
 use parquet::schema::types::*;
 use parquet::{
     column::writer::ColumnWriter,
     file::{
+        metadata::KeyValue,
         properties::WriterProperties,
         writer::{FileWriter, SerializedFileWriter},
     },
 };
 use std::fs::File;
-use std::path::PathBuf;
+use std::path::Path;
 use std::sync::Arc;
-fn generate_parquet_schema(column_names: &Vec<String>, data_types: &Vec<BString>) -> Arc<Type> {
+use utils::error::{Error, Result};
+
+use crate::dumpin::{
+    column_sql_type, declared_types, is_nullable, read_declared_types, split_length, table_name,
+    InsertBatch, DEFAULT_STRING_LENGTH,
+};
+use crate::manifest::Summary;
+use crate::time::convert_epoch;
+
+/// Key of the file metadata holding the declared types of the columns.
+const TYPES_KEY: &str = "tdengine.types";
+
+/// Unit of the Parquet TIMESTAMP logical type for timestamps in `precision`.
+fn time_unit(precision: TimestampPrecision) -> TimeUnit {
+    match precision {
+        TimestampPrecision::Micro => TimeUnit::MICROS(Default::default()),
+        TimestampPrecision::Nano => TimeUnit::NANOS(Default::default()),
+        _ => TimeUnit::MILLIS(Default::default()),
+    }
+}
+
+/// Precision of the timestamps of a Parquet TIMESTAMP logical type in `unit`.
+fn unit_precision(unit: &TimeUnit) -> TimestampPrecision {
+    match unit {
+        TimeUnit::MICROS(_) => TimestampPrecision::Micro,
+        TimeUnit::NANOS(_) => TimestampPrecision::Nano,
+        TimeUnit::MILLIS(_) => TimestampPrecision::Milli,
+    }
+}
+
+fn generate_parquet_schema(
+    table: &str,
+    column_names: &[String],
+    data_types: &[BString],
+    precision: TimestampPrecision,
+) -> Result<Arc<Type>> {
     let mut fields = vec![];
 
     for i in 0..column_names.len() {
-        match data_types[i].to_string().as_str() {
-            "TINYINT" => fields.push(Arc::new(
-                Type::primitive_type_builder(&column_names[i], PhysicalType::INT32)
-                    .with_repetition(Repetition::REQUIRED)
-                    .with_converted_type(ConvertedType::INT_8)
-                    .build()
-                    .unwrap(),
-            )),
-            "TINYINT UNSIGNED" => fields.push(Arc::new(
-                Type::primitive_type_builder(&column_names[i], PhysicalType::INT32)
-                    .with_repetition(Repetition::REQUIRED)
-                    .with_converted_type(ConvertedType::UINT_8)
-                    .build()
-                    .unwrap(),
-            )),
-            "SMALLINT" => fields.push(Arc::new(
-                Type::primitive_type_builder(&column_names[i], PhysicalType::INT32)
-                    .with_repetition(Repetition::REQUIRED)
-                    .with_converted_type(ConvertedType::INT_16)
-                    .build()
-                    .unwrap(),
-            )),
-            "SMALLINT UNSIGNED" => fields.push(Arc::new(
-                Type::primitive_type_builder(&column_names[i], PhysicalType::INT32)
-                    .with_repetition(Repetition::REQUIRED)
-                    .with_converted_type(ConvertedType::UINT_16)
-                    .build()
-                    .unwrap(),
-            )),
-            "INT" => fields.push(Arc::new(
-                Type::primitive_type_builder(&column_names[i], PhysicalType::INT32)
-                    .with_repetition(Repetition::REQUIRED)
-                    .build()
-                    .unwrap(),
-            )),
-            "INT UNSIGNED" => fields.push(Arc::new(
-                Type::primitive_type_builder(&column_names[i], PhysicalType::INT32)
-                    .with_repetition(Repetition::REQUIRED)
-                    .with_converted_type(ConvertedType::UINT_32)
-                    .build()
-                    .unwrap(),
-            )),
-            "BIGINT" => fields.push(Arc::new(
-                Type::primitive_type_builder(&column_names[i], PhysicalType::INT64)
-                    .with_repetition(Repetition::REQUIRED)
-                    .build()
-                    .unwrap(),
-            )),
-            "TIMESTAMP" => fields.push(Arc::new(
-                Type::primitive_type_builder(&column_names[i], PhysicalType::INT64)
-                    .with_repetition(Repetition::REQUIRED)
-                    .with_logical_type(Some(LogicalType::TIMESTAMP(TimestampType {
-                        is_adjusted_to_u_t_c: false,
-                        unit: TimeUnit::MILLIS(Default::default()),
-                    })))
-                    .build()
-                    .unwrap(),
-            )),
-            "BIGINT UNSIGNED" => fields.push(Arc::new(
-                Type::primitive_type_builder(&column_names[i], PhysicalType::INT64)
-                    .with_repetition(Repetition::REQUIRED)
-                    .with_converted_type(ConvertedType::UINT_64)
-                    .build()
-                    .unwrap(),
-            )),
-            "FLOAT" => fields.push(Arc::new(
-                Type::primitive_type_builder(&column_names[i], PhysicalType::FLOAT)
-                    .with_repetition(Repetition::REQUIRED)
-                    .build()
-                    .unwrap(),
-            )),
-            "DOUBLE" => fields.push(Arc::new(
-                Type::primitive_type_builder(&column_names[i], PhysicalType::DOUBLE)
-                    .with_repetition(Repetition::REQUIRED)
-                    .build()
-                    .unwrap(),
-            )),
-            "BINARY" => fields.push(Arc::new(
-                Type::primitive_type_builder(&column_names[i], PhysicalType::BYTE_ARRAY)
-                    .with_repetition(Repetition::REQUIRED)
-                    .with_id(8)
-                    .build()
-                    .unwrap(),
-            )),
-            "NCHAR" => fields.push(Arc::new(
-                Type::primitive_type_builder(&column_names[i], PhysicalType::BYTE_ARRAY)
-                    .with_repetition(Repetition::REQUIRED)
-                    .with_logical_type(Some(LogicalType::STRING(Default::default())))
-                    .with_id(8)
-                    .build()
-                    .unwrap(),
-            )),
-            "BOOL" => fields.push(Arc::new(
-                Type::primitive_type_builder(&column_names[i], PhysicalType::BOOLEAN)
-                    .with_repetition(Repetition::REQUIRED)
-                    .build()
-                    .unwrap(),
-            )),
-            _ => unreachable!("unexpected data type, please contact the author to fix!"),
-        }
+        let name = &column_names[i];
+        let data_type = data_types[i].to_string();
+        // the declared length goes to the key-value metadata of the file
+        let builder = match split_length(&data_type).0 {
+            "TINYINT" => Type::primitive_type_builder(name, PhysicalType::INT32)
+                .with_converted_type(ConvertedType::INT_8),
+            "TINYINT UNSIGNED" => Type::primitive_type_builder(name, PhysicalType::INT32)
+                .with_converted_type(ConvertedType::UINT_8),
+            "SMALLINT" => Type::primitive_type_builder(name, PhysicalType::INT32)
+                .with_converted_type(ConvertedType::INT_16),
+            "SMALLINT UNSIGNED" => Type::primitive_type_builder(name, PhysicalType::INT32)
+                .with_converted_type(ConvertedType::UINT_16),
+            "INT" => Type::primitive_type_builder(name, PhysicalType::INT32),
+            "INT UNSIGNED" => Type::primitive_type_builder(name, PhysicalType::INT32)
+                .with_converted_type(ConvertedType::UINT_32),
+            "BIGINT" => Type::primitive_type_builder(name, PhysicalType::INT64),
+            "TIMESTAMP" => Type::primitive_type_builder(name, PhysicalType::INT64)
+                .with_logical_type(Some(LogicalType::TIMESTAMP(TimestampType {
+                    is_adjusted_to_u_t_c: false,
+                    unit: time_unit(precision),
+                }))),
+            "BIGINT UNSIGNED" => Type::primitive_type_builder(name, PhysicalType::INT64)
+                .with_converted_type(ConvertedType::UINT_64),
+            "FLOAT" => Type::primitive_type_builder(name, PhysicalType::FLOAT),
+            "DOUBLE" => Type::primitive_type_builder(name, PhysicalType::DOUBLE),
+            "BINARY" | "VARCHAR" | "VARBINARY" | "GEOMETRY" => {
+                Type::primitive_type_builder(name, PhysicalType::BYTE_ARRAY)
+            }
+            "NCHAR" => Type::primitive_type_builder(name, PhysicalType::BYTE_ARRAY)
+                .with_logical_type(Some(LogicalType::STRING(Default::default()))),
+            "JSON" => Type::primitive_type_builder(name, PhysicalType::BYTE_ARRAY)
+                .with_logical_type(Some(LogicalType::JSON(Default::default())))
+                .with_converted_type(ConvertedType::JSON),
+            "BOOL" => Type::primitive_type_builder(name, PhysicalType::BOOLEAN),
+            data_type => return Err(Error::unsupported_type(table, name, data_type)),
+        };
+        let repetition = if is_nullable(i) {
+            Repetition::OPTIONAL
+        } else {
+            Repetition::REQUIRED
+        };
+        let field = builder
+            .with_repetition(repetition)
+            .build()
+            .map_err(|err| Error::schema(table, err.to_string()))?;
+        fields.push(Arc::new(field));
     }
 
-    Arc::new(
-        Type::group_type_builder("schema")
-            .with_fields(&mut fields)
-            .build()
-            .unwrap(),
-    )
+    let schema = Type::group_type_builder("schema")
+        .with_fields(&mut fields)
+        .build()
+        .map_err(|err| Error::schema(table, err.to_string()))?;
+    Ok(Arc::new(schema))
 }
 
-pub fn parquet_dumpout(
-    file: File,
-    column_names: &Vec<String>,
-    data_types: &Vec<BString>,
-    rows: Vec<Vec<libtaos::Field>>,
-) -> i64 {
-    let schema = generate_parquet_schema(&column_names, &data_types);
-    let props = Arc::new(WriterProperties::builder().build());
-    let mut writer = SerializedFileWriter::new(file, schema, props).unwrap();
+/// Default maximum number of rows in a Parquet row group.
+pub const DEFAULT_ROW_GROUP_ROWS: usize = 1024 * 1024;
+/// Default maximum size in bytes of the values buffered for a Parquet row group.
+pub const DEFAULT_ROW_GROUP_BYTES: usize = 128 * 1024 * 1024;
 
-    let mut num_points: i64 = 0;
+/// Limits at which the buffered rows are flushed as a Parquet row group, whichever is hit first.
+#[derive(Debug, Clone, Copy)]
+pub struct RowGroupSize {
+    pub rows: usize,
+    pub bytes: usize,
+}
 
-    for row in rows {
-        let mut row_group_writer = writer.next_row_group().unwrap();
-        for field in row {
-            let data_writer = row_group_writer.next_column().unwrap();
-            if let Some(mut writer) = data_writer {
-                match writer {
-                    ColumnWriter::Int32ColumnWriter(ref mut typed) => {
-                        let values;
-                        match field {
-                            Field::Null => todo!(),
-                            Field::TinyInt(v) => {
-                                values = vec![v as i32];
-                            }
-                            Field::UTinyInt(v) => {
-                                values = vec![v as i32];
-                            }
-                            Field::SmallInt(v) => {
-                                values = vec![v as i32];
-                            }
-                            Field::USmallInt(v) => {
-                                values = vec![v as i32];
-                            }
-                            Field::Int(v) => {
-                                values = vec![v];
-                            }
-                            Field::UInt(v) => {
-                                values = vec![v as i32];
-                            }
-                            _ => unreachable!(
-                                "unexpected data type, please contact the author to fix!"
-                            ),
-                        }
+impl Default for RowGroupSize {
+    fn default() -> Self {
+        RowGroupSize {
+            rows: DEFAULT_ROW_GROUP_ROWS,
+            bytes: DEFAULT_ROW_GROUP_BYTES,
+        }
+    }
+}
 
-                        num_points += typed.write_batch(&values[..], None, None).unwrap() as i64;
-                    }
-                    ColumnWriter::BoolColumnWriter(ref mut typed) => {
-                        let values = vec![*field.as_bool().unwrap()];
-                        num_points += typed.write_batch(&values[..], None, None).unwrap() as i64;
-                    }
-                    ColumnWriter::Int64ColumnWriter(ref mut typed) => {
-                        let values;
-                        match field {
-                            Field::Null => todo!(),
-                            Field::BigInt(v) => {
-                                values = vec![v];
-                            }
-                            Field::Timestamp(v) => {
-                                values = vec![v.as_raw_timestamp()];
-                            }
-                            Field::UBigInt(v) => {
-                                values = vec![v as i64];
-                            }
-                            _ => unreachable!(
-                                "unexpected data type, please contact the author to fix!"
-                            ),
-                        }
-                        num_points += typed.write_batch(&values[..], None, None).unwrap() as i64;
-                    }
-                    ColumnWriter::FloatColumnWriter(ref mut typed) => {
-                        let values = vec![*field.as_float().unwrap()];
-                        num_points += typed.write_batch(&values[..], None, None).unwrap() as i64;
-                    }
-                    ColumnWriter::DoubleColumnWriter(ref mut typed) => {
-                        let values = vec![*field.as_double().unwrap()];
-                        num_points += typed.write_batch(&values[..], None, None).unwrap() as i64;
-                    }
-                    ColumnWriter::ByteArrayColumnWriter(ref mut typed) => {
-                        let values;
-                        match field {
-                            Field::Null => todo!(),
-                            Field::Binary(v) => {
-                                let binary_data = (&*v).to_vec();
-                                values = vec![parquet::data_type::ByteArray::from(binary_data)];
-                            }
-                            Field::NChar(_) => {
-                                values = vec![parquet::data_type::ByteArray::from(
-                                    field.as_nchar().unwrap(),
-                                )];
-                            }
-                            _ => unreachable!(
-                                "unexpected data type, please contact the author to fix!"
-                            ),
-                        }
+/// Values of one column buffered until the row group is written.
+enum ColumnValues {
+    Bool(Vec<bool>),
+    Int32(Vec<i32>),
+    Int64(Vec<i64>),
+    Float(Vec<f32>),
+    Double(Vec<f64>),
+    ByteArray(Vec<ByteArray>),
+}
 
-                        num_points += typed.write_batch(&values[..], None, None).unwrap() as i64;
-                    }
-                    ColumnWriter::FixedLenByteArrayColumnWriter(_) => todo!(),
-                    _ => unreachable!("unexpected data type, please contact the author to fix!"),
-                }
-                row_group_writer.close_column(writer).unwrap();
+struct ColumnBuffer {
+    values: ColumnValues,
+    /// Definition levels of an OPTIONAL column, `None` for a REQUIRED one.
+    def_levels: Option<Vec<i16>>,
+}
+
+impl ColumnBuffer {
+    fn new(field: &Type) -> parquet::errors::Result<Self> {
+        let values = match field.get_physical_type() {
+            PhysicalType::BOOLEAN => ColumnValues::Bool(vec![]),
+            PhysicalType::INT32 => ColumnValues::Int32(vec![]),
+            PhysicalType::INT64 => ColumnValues::Int64(vec![]),
+            PhysicalType::FLOAT => ColumnValues::Float(vec![]),
+            PhysicalType::DOUBLE => ColumnValues::Double(vec![]),
+            PhysicalType::BYTE_ARRAY => ColumnValues::ByteArray(vec![]),
+            physical_type => {
+                return Err(ParquetError::NYI(format!(
+                    "buffering {} columns",
+                    physical_type
+                )))
+            }
+        };
+        let def_levels = match field.get_basic_info().repetition() {
+            Repetition::OPTIONAL => Some(vec![]),
+            _ => None,
+        };
+        Ok(ColumnBuffer { values, def_levels })
+    }
+
+    /// Buffer a value and return its size in bytes, or give the value back when it does not fit
+    /// the column type.
+    fn push(&mut self, field: Field) -> std::result::Result<usize, Field> {
+        if let Some(def_levels) = &mut self.def_levels {
+            if field == Field::Null {
+                def_levels.push(0);
+                return Ok(0);
             }
+            def_levels.push(1);
         }
-        writer.close_row_group(row_group_writer).unwrap();
+        let size = match (&mut self.values, field) {
+            (ColumnValues::Bool(values), Field::Bool(v)) => {
+                values.push(v);
+                1
+            }
+            // unsigned values are stored bit for bit, their UINT_* type tells readers so
+            (ColumnValues::Int32(values), field) => {
+                values.push(match field {
+                    Field::TinyInt(v) => v as i32,
+                    Field::UTinyInt(v) => v as i32,
+                    Field::SmallInt(v) => v as i32,
+                    Field::USmallInt(v) => v as i32,
+                    Field::Int(v) => v,
+                    Field::UInt(v) => v as i32,
+                    field => return Err(field),
+                });
+                4
+            }
+            (ColumnValues::Int64(values), field) => {
+                values.push(match field {
+                    Field::BigInt(v) => v,
+                    Field::Timestamp(v) => v.as_raw_timestamp(),
+                    Field::UBigInt(v) => v as i64,
+                    field => return Err(field),
+                });
+                8
+            }
+            (ColumnValues::Float(values), Field::Float(v)) => {
+                values.push(v);
+                4
+            }
+            (ColumnValues::Double(values), Field::Double(v)) => {
+                values.push(v);
+                8
+            }
+            (ColumnValues::ByteArray(values), field) => {
+                let bytes = match field {
+                    Field::Binary(v) => v.to_vec(),
+                    Field::NChar(v) => v.into_bytes(),
+                    field => return Err(field),
+                };
+                let size = bytes.len();
+                values.push(ByteArray::from(bytes));
+                size
+            }
+            (_, field) => return Err(field),
+        };
+        Ok(size)
     }
-    writer.close().unwrap();
-    num_points
-}
-#[tokio::main]
-pub async fn parquet_dumpin(file_list: &Vec<PathBuf>, taos: Taos) {
-    for file in file_list {
-        let parquet_reader = SerializedFileReader::try_from(file.to_str().unwrap()).unwrap();
-        let mut sql = "create table m1 (".to_string();
-        let read_schema = parquet_reader.metadata().file_metadata().schema();
-        let mut index = 0;
-        for field in read_schema.get_fields() {
-            if index != 0 {
-                sql += ",";
+
+    /// Write all buffered values with a single batch and clear the buffer.
+    fn write(&mut self, writer: &mut ColumnWriter) -> parquet::errors::Result<usize> {
+        let def_levels = self.def_levels.as_deref();
+        let written = match (&mut self.values, writer) {
+            (ColumnValues::Bool(values), ColumnWriter::BoolColumnWriter(typed)) => {
+                typed.write_batch(values, def_levels, None)
             }
-            index += 1;
-            sql += field.get_basic_info().name();
-            match field.get_physical_type() {
-                PhysicalType::BOOLEAN => sql += " bool",
-                PhysicalType::INT32 => match field.get_basic_info().converted_type() {
-                    ConvertedType::UINT_8 => sql += " tinyint unsigned",
-                    ConvertedType::UINT_16 => sql += " smallint unsigned",
-                    ConvertedType::UINT_32 => sql += " int unsigned",
-                    ConvertedType::INT_8 => sql += " tinyint",
-                    ConvertedType::INT_16 => sql += " smallint",
-                    ConvertedType::NONE => sql += " int",
-                    _ => unreachable!("unexpected data type, please contact the author to fix!"),
-                },
-                PhysicalType::INT64 => match field.get_basic_info().logical_type() {
-                    Some(_) => sql += " timestamp",
-                    None => match field.get_basic_info().converted_type() {
-                        ConvertedType::NONE => sql += " bigint",
-                        ConvertedType::UINT_64 => sql += " bigint unsigned",
-                        _ => {
-                            unreachable!("unexpected data type, please contact the author to fix!")
-                        }
-                    },
-                },
-                PhysicalType::FLOAT => sql += " float",
-                PhysicalType::DOUBLE => sql += " double",
-                PhysicalType::BYTE_ARRAY => {
-                    let length = field.get_basic_info().id();
-                    match field.get_basic_info().logical_type() {
-                        Some(_) => sql += &format!(" nchar({})", length),
-                        None => sql += &format!(" binary({})", length),
-                    }
-                }
-                _ => unreachable!("unexpected data type, please contact the author to fix!"),
+            (ColumnValues::Int32(values), ColumnWriter::Int32ColumnWriter(typed)) => {
+                typed.write_batch(values, def_levels, None)
+            }
+            (ColumnValues::Int64(values), ColumnWriter::Int64ColumnWriter(typed)) => {
+                typed.write_batch(values, def_levels, None)
+            }
+            (ColumnValues::Float(values), ColumnWriter::FloatColumnWriter(typed)) => {
+                typed.write_batch(values, def_levels, None)
             }
+            (ColumnValues::Double(values), ColumnWriter::DoubleColumnWriter(typed)) => {
+                typed.write_batch(values, def_levels, None)
+            }
+            (ColumnValues::ByteArray(values), ColumnWriter::ByteArrayColumnWriter(typed)) => {
+                typed.write_batch(values, def_levels, None)
+            }
+            _ => Err(ParquetError::General(
+                "column writer does not match the buffered values".to_string(),
+            )),
+        }?;
+        match &mut self.values {
+            ColumnValues::Bool(values) => values.clear(),
+            ColumnValues::Int32(values) => values.clear(),
+            ColumnValues::Int64(values) => values.clear(),
+            ColumnValues::Float(values) => values.clear(),
+            ColumnValues::Double(values) => values.clear(),
+            ColumnValues::ByteArray(values) => values.clear(),
         }
-        sql += ")";
-        println!("sql: {}", sql);
-        assert_eq!(taos.query(sql.as_str()).await.is_ok(), true);
-        let mut sql = "insert into m1 values".to_string();
-        for row in parquet_reader {
-            sql += "(";
-            let mut count = 0;
-            for (_, col) in row.get_column_iter() {
-                if count != 0 {
-                    sql += ",";
-                }
-                count += 1;
-                match col {
-                    parquet::record::Field::Null => todo!(),
-                    parquet::record::Field::Bool(v) => sql += &v.to_string(),
-                    parquet::record::Field::Byte(v) => sql += &v.to_string(),
-                    parquet::record::Field::Short(v) => sql += &v.to_string(),
-                    parquet::record::Field::Int(v) => sql += &v.to_string(),
-                    parquet::record::Field::Long(v) => sql += &v.to_string(),
-                    parquet::record::Field::UByte(v) => sql += &v.to_string(),
-                    parquet::record::Field::UShort(v) => sql += &v.to_string(),
-                    parquet::record::Field::UInt(v) => sql += &v.to_string(),
-                    parquet::record::Field::ULong(v) => sql += &v.to_string(),
-                    parquet::record::Field::Float(v) => sql += &v.to_string(),
-                    parquet::record::Field::Double(v) => sql += &v.to_string(),
-                    parquet::record::Field::Str(v) => sql += format!("\'{}\'", &v).as_str(),
-                    parquet::record::Field::Bytes(v) => {
-                        sql += format!("\'{}\'", std::str::from_utf8(v.data()).unwrap()).as_str()
-                    }
-                    parquet::record::Field::TimestampMillis(v) => sql += &v.to_string(),
-                    parquet::record::Field::TimestampMicros(v) => sql += &v.to_string(),
-                    parquet::record::Field::Group(v) => sql += &v.to_string(),
-                    _ => todo!(),
-                }
+        if let Some(def_levels) = &mut self.def_levels {
+            def_levels.clear();
+        }
+        Ok(written)
+    }
+}
+
+fn write_row_group(
+    writer: &mut SerializedFileWriter<File>,
+    buffers: &mut [ColumnBuffer],
+) -> parquet::errors::Result<i64> {
+    let mut num_points = 0;
+    let mut row_group_writer = writer.next_row_group()?;
+    for buffer in buffers.iter_mut() {
+        let mut column_writer = row_group_writer.next_column()?.ok_or_else(|| {
+            ParquetError::General("fewer columns in the schema than buffered".to_string())
+        })?;
+        num_points += buffer.write(&mut column_writer)? as i64;
+        row_group_writer.close_column(column_writer)?;
+    }
+    writer.close_row_group(row_group_writer)?;
+    Ok(num_points)
+}
+
+/// Write the rows of `table` to a Parquet file at `path` and return the number of values written.
+pub fn parquet_dumpout(
+    path: &Path,
+    table: &str,
+    column_names: &[String],
+    data_types: &[BString],
+    rows: impl Iterator<Item = Result<Vec<Vec<libtaos::Field>>>>,
+    row_group_size: RowGroupSize,
+    precision: TimestampPrecision,
+) -> Result<i64> {
+    let schema = generate_parquet_schema(table, column_names, data_types, precision)?;
+    let mut buffers = schema
+        .get_fields()
+        .iter()
+        .map(|field| ColumnBuffer::new(field))
+        .collect::<parquet::errors::Result<Vec<_>>>()
+        .map_err(|err| Error::encode(path, err))?;
+    let props = Arc::new(
+        WriterProperties::builder()
+            .set_max_row_group_size(row_group_size.rows)
+            .set_key_value_metadata(Some(vec![KeyValue::new(
+                TYPES_KEY.to_string(),
+                declared_types(column_names, data_types),
+            )]))
+            .build(),
+    );
+    let file = File::create(path).map_err(|err| Error::io(path, err))?;
+    let mut writer =
+        SerializedFileWriter::new(file, schema, props).map_err(|err| Error::encode(path, err))?;
+
+    let mut num_points: i64 = 0;
+    let mut buffered_rows = 0;
+    let mut buffered_bytes = 0;
+    for rows in rows {
+        for row in rows? {
+            for ((buffer, field), column) in buffers.iter_mut().zip(row).zip(column_names) {
+                buffered_bytes += buffer.push(field).map_err(|field| {
+                    Error::unsupported_type(table, column, format!("{:?}", field.data_type()))
+                })?;
+            }
+            buffered_rows += 1;
+            if buffered_rows >= row_group_size.rows || buffered_bytes >= row_group_size.bytes {
+                num_points += write_row_group(&mut writer, &mut buffers)
+                    .map_err(|err| Error::encode(path, err))?;
+                buffered_rows = 0;
+                buffered_bytes = 0;
             }
-            sql += ")"
         }
-        assert_eq!(taos.query(sql.as_str()).await.is_ok(), true);
+    }
+    if buffered_rows > 0 {
+        num_points +=
+            write_row_group(&mut writer, &mut buffers).map_err(|err| Error::encode(path, err))?;
+    }
+    writer.close().map_err(|err| Error::encode(path, err))?;
+    Ok(num_points)
+}
+
+/// Count the rows of a Parquet data file and find the range of its timestamp column.
+pub fn parquet_summary(file: &Path) -> Result<Summary> {
+    let parquet_reader = File::open(file)
+        .map_err(|err| Error::io(file, err))
+        .and_then(|f| SerializedFileReader::new(f).map_err(|err| Error::decode(file, err)))?;
+    let mut summary = Summary::default();
+    for row in parquet_reader {
+        let ts = match row.get_column_iter().next() {
+            Some((_, parquet::record::Field::TimestampMillis(v))) => Some(*v as i64),
+            Some((_, parquet::record::Field::TimestampMicros(v))) => Some(*v as i64),
+            Some((_, parquet::record::Field::Long(v))) => Some(*v),
+            _ => None,
+        };
+        summary.add(ts);
+    }
+    Ok(summary)
+}
+
+pub async fn parquet_dumpin(
+    taos: &Taos,
+    file: &Path,
+    batch_rows: usize,
+    precision: TimestampPrecision,
+) -> Result<()> {
+    let table = table_name(file)?;
+    let parquet_reader = File::open(file)
+        .map_err(|err| Error::io(file, err))
+        .and_then(|f| SerializedFileReader::new(f).map_err(|err| Error::decode(file, err)))?;
+    let mut sql = format!("create table if not exists {} (", table);
+    let file_metadata = parquet_reader.metadata().file_metadata();
+    let read_schema = file_metadata.schema();
+    // the declared types tell the lengths of string columns, and BINARY, VARBINARY and GEOMETRY
+    // columns apart, all of them plain byte arrays
+    let declared = read_declared_types(
+        file_metadata
+            .key_value_metadata()
+            .iter()
+            .flat_map(|metadata| metadata.iter())
+            .find(|metadata| metadata.key == TYPES_KEY)
+            .and_then(|metadata| metadata.value.as_deref()),
+    );
+    // unit of every TIMESTAMP column, its values converted to the precision of the database
+    let mut units = vec![None; read_schema.get_fields().len()];
+    for (index, field) in read_schema.get_fields().iter().enumerate() {
+        if index != 0 {
+            sql += ",";
+        }
+        let info = field.get_basic_info();
+        let unsupported = || {
+            Error::unsupported_type(
+                table,
+                info.name(),
+                format!("{} ({})", field.get_physical_type(), info.converted_type()),
+            )
+        };
+        if let Some(LogicalType::TIMESTAMP(TimestampType { unit, .. })) = info.logical_type() {
+            units[index] = Some(unit_precision(&unit));
+        }
+        sql += info.name();
+        if let Some(data_type) = declared.get(info.name()) {
+            sql += " ";
+            sql += &column_sql_type(table, info.name(), data_type)?;
+            continue;
+        }
+        match field.get_physical_type() {
+            PhysicalType::BOOLEAN => sql += " bool",
+            PhysicalType::INT32 => match info.converted_type() {
+                ConvertedType::UINT_8 => sql += " tinyint unsigned",
+                ConvertedType::UINT_16 => sql += " smallint unsigned",
+                ConvertedType::UINT_32 => sql += " int unsigned",
+                ConvertedType::INT_8 => sql += " tinyint",
+                ConvertedType::INT_16 => sql += " smallint",
+                ConvertedType::NONE => sql += " int",
+                _ => return Err(unsupported()),
+            },
+            PhysicalType::INT64 => match info.logical_type() {
+                Some(LogicalType::TIMESTAMP(_)) => sql += " timestamp",
+                Some(_) => return Err(unsupported()),
+                None => match info.converted_type() {
+                    ConvertedType::NONE => sql += " bigint",
+                    ConvertedType::UINT_64 => sql += " bigint unsigned",
+                    _ => return Err(unsupported()),
+                },
+            },
+            PhysicalType::FLOAT => sql += " float",
+            PhysicalType::DOUBLE => sql += " double",
+            PhysicalType::BYTE_ARRAY => match info.logical_type() {
+                Some(_) => sql += &format!(" nchar({})", DEFAULT_STRING_LENGTH),
+                None => sql += &format!(" binary({})", DEFAULT_STRING_LENGTH),
+            },
+            _ => return Err(unsupported()),
+        }
+    }
+    sql += ")";
+    trace!("create table: {}", sql);
+    taos.query(&sql)
+        .await
+        .map_err(|err| Error::query(&sql, err))?;
+    let mut batch = InsertBatch::new(taos, table, file, batch_rows, precision).await?;
+    for row in parquet_reader {
+        let mut fields = vec![];
+        for (index, (name, col)) in row.get_column_iter().enumerate() {
+            let field = match col {
+                parquet::record::Field::Null => Field::Null,
+                parquet::record::Field::Bool(v) => Field::Bool(*v),
+                parquet::record::Field::Byte(v) => Field::TinyInt(*v),
+                parquet::record::Field::Short(v) => Field::SmallInt(*v),
+                parquet::record::Field::Int(v) => Field::Int(*v),
+                parquet::record::Field::Long(v) => Field::BigInt(*v),
+                parquet::record::Field::UByte(v) => Field::UTinyInt(*v),
+                parquet::record::Field::UShort(v) => Field::USmallInt(*v),
+                parquet::record::Field::UInt(v) => Field::UInt(*v),
+                parquet::record::Field::ULong(v) => Field::UBigInt(*v),
+                parquet::record::Field::Float(v) => Field::Float(*v),
+                parquet::record::Field::Double(v) => Field::Double(*v),
+                parquet::record::Field::Str(v) => Field::NChar(v.clone()),
+                parquet::record::Field::Bytes(v) => Field::Binary(BString::from(v.data())),
+                parquet::record::Field::TimestampMillis(v) => Field::BigInt(*v as i64),
+                parquet::record::Field::TimestampMicros(v) => Field::BigInt(*v as i64),
+                col => return Err(Error::unsupported_type(table, name, format!("{:?}", col))),
+            };
+            let field = match (field, units[index]) {
+                (Field::BigInt(v), Some(unit)) => Field::BigInt(convert_epoch(v, unit, precision)),
+                (field, _) => field,
+            };
+            fields.push(field);
+        }
+        batch.push(fields).await?;
+    }
+    batch.flush().await
+}
+
+#[cfg(test)]
+mod tests {
+    use parquet::record::Field as ParquetField;
+
+    use super::*;
+
+    #[test]
+    fn json_is_annotated() {
+        let column_names = ["ts", "j"].map(String::from);
+        let data_types = ["TIMESTAMP", "JSON"].map(BString::from);
+        let schema =
+            generate_parquet_schema("t", &column_names, &data_types, TimestampPrecision::Milli)
+                .unwrap();
+        let json = schema.get_fields()[1].get_basic_info();
+        assert_eq!(json.converted_type(), ConvertedType::JSON);
+        assert_eq!(
+            json.logical_type(),
+            Some(LogicalType::JSON(Default::default()))
+        );
+    }
+
+    #[test]
+    fn rows_round_trip_across_row_groups() {
+        let path = std::env::temp_dir().join(format!("row-groups-{}.parquet", std::process::id()));
+        let column_names = ["ts", "v", "s"].map(String::from);
+        let data_types = ["TIMESTAMP", "INT", "NCHAR(8)"].map(BString::from);
+        let rows = (0..5)
+            .map(|i| {
+                vec![
+                    Field::Timestamp(Timestamp::new(i, TimestampPrecision::Milli)),
+                    match i % 2 {
+                        0 => Field::Int(i as i32),
+                        _ => Field::Null,
+                    },
+                    match i % 3 {
+                        0 => Field::Null,
+                        _ => Field::NChar(format!("r{}", i)),
+                    },
+                ]
+            })
+            .collect::<Vec<_>>();
+        let row_group_size = RowGroupSize {
+            rows: 2,
+            ..Default::default()
+        };
+        let points = parquet_dumpout(
+            &path,
+            "t",
+            &column_names,
+            &data_types,
+            vec![Ok(rows[..3].to_vec()), Ok(rows[3..].to_vec())].into_iter(),
+            row_group_size,
+            TimestampPrecision::Milli,
+        )
+        .unwrap();
+        // NULL values are not counted
+        assert_eq!(points, 11);
+
+        let reader = SerializedFileReader::new(File::open(&path).unwrap()).unwrap();
+        let metadata = reader.metadata();
+        let group_rows = metadata
+            .row_groups()
+            .iter()
+            .map(|group| group.num_rows())
+            .collect::<Vec<_>>();
+        assert_eq!(group_rows, [2, 2, 1]);
+        let repetitions = metadata
+            .file_metadata()
+            .schema()
+            .get_fields()
+            .iter()
+            .map(|field| field.get_basic_info().repetition())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            repetitions,
+            [
+                Repetition::REQUIRED,
+                Repetition::OPTIONAL,
+                Repetition::OPTIONAL
+            ]
+        );
+        let read = reader
+            .into_iter()
+            .map(|row| {
+                row.get_column_iter()
+                    .map(|(_, field)| field.clone())
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        let summary = parquet_summary(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            (summary.rows, summary.min_ts, summary.max_ts),
+            (5, Some(0), Some(4))
+        );
+        let expected = (0..5)
+            .map(|i: i32| {
+                vec![
+                    ParquetField::TimestampMillis(i as u64),
+                    match i % 2 {
+                        0 => ParquetField::Int(i),
+                        _ => ParquetField::Null,
+                    },
+                    match i % 3 {
+                        0 => ParquetField::Null,
+                        _ => ParquetField::Str(format!("r{}", i)),
+                    },
+                ]
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(read, expected);
     }
 }
@@ -0,0 +1,367 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, BufWriter, Lines, Write};
+use std::path::Path;
+
+use bstr::BString;
+use libtaos::{Field, Taos};
+use utils::error::{Error, Result};
+use utils::get_all_files;
+
+use crate::dumpin::{table_name, with_if_not_exists, DEFAULT_BATCH_ROWS, MAX_SQL_LENGTH};
+use crate::dumpout::column_sql_value;
+use crate::manifest::Summary;
+
+/// DDL of the database and its super tables, repeated at the top of every SQL data file so that
+/// each of them can be run on its own with `taos -f`.
+#[derive(Debug, Default)]
+pub struct SchemaDdl {
+    database: Option<String>,
+    /// DDL by super table name.
+    stables: HashMap<String, String>,
+}
+
+impl SchemaDdl {
+    /// Read the DDL files dumped out of database `name`.
+    pub fn load(dir_path: &str, name: &str) -> Result<Self> {
+        let mut ddl = SchemaDdl::default();
+        let path = Path::new(dir_path).join(format!("{}.db", name));
+        if path.exists() {
+            let database = fs::read_to_string(&path).map_err(|err| Error::io(&path, err))?;
+            ddl.database = Some(with_if_not_exists(&database));
+        }
+        for path in get_all_files(dir_path, "stb")? {
+            let stables = fs::read_to_string(&path).map_err(|err| Error::io(&path, err))?;
+            for stable in stables.lines().filter(|line| !line.trim().is_empty()) {
+                // CREATE TABLE <name> ...
+                if let Some(stable_name) = stable.split_whitespace().nth(2) {
+                    ddl.stables.insert(
+                        stable_name.trim_matches('`').to_string(),
+                        with_if_not_exists(stable),
+                    );
+                }
+            }
+        }
+        Ok(ddl)
+    }
+
+    /// Statements that create database `name`, the super table and the table itself from
+    /// `table_ddl`.
+    pub fn preamble(&self, name: &str, stable: Option<&str>, table_ddl: &str) -> Vec<String> {
+        let mut statements = vec![];
+        if let Some(database) = &self.database {
+            statements.push(database.clone());
+        }
+        statements.push(format!("USE {}", name));
+        if let Some(ddl) = stable.and_then(|stable| self.stables.get(stable)) {
+            statements.push(ddl.clone());
+        }
+        statements.push(with_if_not_exists(table_ddl));
+        statements
+    }
+}
+
+/// DDL of a child or normal table, as `show create table` gives it.
+pub async fn table_ddl(taos: &Taos, name: &str, table: &str) -> Result<String> {
+    let sql = format!("show create table {}.{}", name, table);
+    let rows = taos
+        .query(&sql)
+        .await
+        .map_err(|err| Error::query(&sql, err))?;
+    match rows.rows.first().and_then(|row| row.get(1)) {
+        Some(Field::Binary(v)) => Ok(v.to_string()),
+        _ => Err(Error::schema(table, format!("no DDL returned by {}", sql))),
+    }
+}
+
+/// Write `preamble` and the rows of `table` as a SQL script at `path`, one statement per line
+/// with INSERTs of at most [`DEFAULT_BATCH_ROWS`] rows, and return the number of values written.
+/// VARBINARY values are written in hex, which TDengine decodes on restore, and GEOMETRY ones as
+/// WKT.
+pub fn sql_dumpout(
+    path: &Path,
+    table: &str,
+    column_names: &[String],
+    data_types: &[BString],
+    preamble: &[String],
+    rows: impl Iterator<Item = Result<Vec<Vec<Field>>>>,
+) -> Result<i64> {
+    let file = File::create(path).map_err(|err| Error::io(path, err))?;
+    let mut writer = BufWriter::new(file);
+    let mut head = format!("-- table {}\n", table);
+    for statement in preamble {
+        head += &format!("{};\n", statement);
+    }
+    writer
+        .write_all(head.as_bytes())
+        .map_err(|err| Error::io(path, err))?;
+
+    let insert = format!("INSERT INTO {} VALUES", table);
+    let mut statement = insert.clone();
+    let mut statement_rows = 0;
+    let mut data_point = 0;
+    for rows in rows {
+        for row in rows? {
+            let mut values = Vec::with_capacity(row.len());
+            for ((field, name), data_type) in row.iter().zip(column_names).zip(data_types) {
+                let value = column_sql_value(field, &data_type.to_string()).ok_or_else(|| {
+                    Error::encode(path, format!("value of column {} has no SQL form", name))
+                })?;
+                values.push(value);
+            }
+            data_point += values.len() as i64;
+            let values = format!(" ({})", values.join(","));
+            // keep every statement runnable by the TDengine CLI
+            if statement_rows > 0
+                && (statement_rows >= DEFAULT_BATCH_ROWS
+                    || statement.len() + values.len() + 1 > MAX_SQL_LENGTH)
+            {
+                writeln!(writer, "{};", statement).map_err(|err| Error::io(path, err))?;
+                statement.truncate(insert.len());
+                statement_rows = 0;
+            }
+            statement += &values;
+            statement_rows += 1;
+        }
+    }
+    if statement_rows > 0 {
+        writeln!(writer, "{};", statement).map_err(|err| Error::io(path, err))?;
+    }
+    writer.flush().map_err(|err| Error::io(path, err))?;
+    Ok(data_point)
+}
+
+/// Statements of a SQL script, split on the semicolons that end a line outside of a string
+/// literal, and comment lines skipped, within a statement too.
+struct Statements<R> {
+    lines: Lines<R>,
+}
+
+impl<R: BufRead> Iterator for Statements<R> {
+    type Item = std::io::Result<String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut statement = String::new();
+        let mut in_quote = false;
+        let mut escaped = false;
+        for line in self.lines.by_ref() {
+            let line = match line {
+                Ok(line) => line,
+                Err(err) => return Some(Err(err)),
+            };
+            let trimmed = line.trim();
+            if !in_quote
+                && (trimmed.starts_with("--") || statement.is_empty() && trimmed.is_empty())
+            {
+                continue;
+            }
+            for c in line.chars() {
+                if escaped {
+                    escaped = false;
+                } else if in_quote && c == '\\' {
+                    escaped = true;
+                } else if c == '\'' {
+                    in_quote = !in_quote;
+                }
+            }
+            if !statement.is_empty() {
+                statement.push('\n');
+            }
+            statement += &line;
+            if !in_quote && trimmed.ends_with(';') {
+                let statement = statement.trim_end().trim_end_matches(';').trim_end();
+                return Some(Ok(statement.to_string()));
+            }
+        }
+        match statement.trim().is_empty() {
+            true => None,
+            false => Some(Ok(statement)),
+        }
+    }
+}
+
+fn read_statements(file: &Path) -> Result<Statements<BufReader<File>>> {
+    let f = File::open(file).map_err(|err| Error::io(file, err))?;
+    Ok(Statements {
+        lines: BufReader::new(f).lines(),
+    })
+}
+
+/// Whether `statement` starts with the keywords of `prefix`, whatever their case.
+fn starts_with_keywords(statement: &str, prefix: &[&str]) -> bool {
+    let mut words = statement.split_whitespace();
+    prefix.iter().all(|keyword| {
+        words
+            .next()
+            .is_some_and(|word| word.eq_ignore_ascii_case(keyword))
+    })
+}
+
+/// First value of every row of an INSERT statement.
+fn first_values(statement: &str) -> Vec<String> {
+    let mut values = vec![];
+    let mut depth = 0;
+    let mut in_quote = false;
+    let mut escaped = false;
+    let mut value: Option<String> = None;
+    for c in statement.chars() {
+        if escaped {
+            escaped = false;
+        } else if in_quote && c == '\\' {
+            escaped = true;
+        } else if c == '\'' {
+            in_quote = !in_quote;
+        } else if !in_quote && c == '(' {
+            depth += 1;
+            if depth == 1 {
+                value = Some(String::new());
+                continue;
+            }
+        } else if !in_quote && c == ')' {
+            depth -= 1;
+        }
+        if depth == 0 || (!in_quote && c == ',') {
+            if let Some(value) = value.take() {
+                values.push(value.trim().to_string());
+            }
+        } else if let Some(value) = &mut value {
+            value.push(c);
+        }
+    }
+    values
+}
+
+/// Count the rows inserted by a SQL data file and find the range of their timestamps.
+pub fn sql_summary(file: &Path) -> Result<Summary> {
+    let mut summary = Summary::default();
+    for statement in read_statements(file)? {
+        let statement = statement.map_err(|err| Error::io(file, err))?;
+        if starts_with_keywords(&statement, &["INSERT", "INTO"]) {
+            for value in first_values(&statement) {
+                summary.add(value.parse().ok());
+            }
+        }
+    }
+    Ok(summary)
+}
+
+/// Run the statements of a SQL data file but those choosing the database, as dumpin restores
+/// into a database of its own.
+pub async fn sql_dumpin(taos: &Taos, file: &Path) -> Result<()> {
+    let table = table_name(file)?;
+    for statement in read_statements(file)? {
+        let sql = statement.map_err(|err| Error::io(file, err))?;
+        if starts_with_keywords(&sql, &["CREATE", "DATABASE"])
+            || starts_with_keywords(&sql, &["USE"])
+        {
+            continue;
+        }
+        if let Err(source) = taos.query(&sql).await {
+            return Err(match starts_with_keywords(&sql, &["INSERT"]) {
+                true => Error::InsertRejected {
+                    table: table.to_string(),
+                    file: file.to_path_buf(),
+                    sql,
+                    source,
+                },
+                false => Error::query(sql, source),
+            });
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Cursor};
+
+    use super::*;
+
+    fn statements(script: &str) -> io::Result<Vec<String>> {
+        Statements {
+            lines: Cursor::new(script).lines(),
+        }
+        .collect()
+    }
+
+    #[test]
+    fn statements_split_at_line_ends() {
+        let script = "-- header\n\ncreate table t (ts timestamp);\ninsert into t values\n(1);\n";
+        assert_eq!(
+            statements(script).unwrap(),
+            ["create table t (ts timestamp)", "insert into t values\n(1)"]
+        );
+    }
+
+    #[test]
+    fn semicolon_in_literal() {
+        let script = "insert into t values (1, 'a;');\ninsert into t values (2, 'b;\nc;');\n";
+        assert_eq!(
+            statements(script).unwrap(),
+            [
+                "insert into t values (1, 'a;')",
+                "insert into t values (2, 'b;\nc;')"
+            ]
+        );
+    }
+
+    #[test]
+    fn escaped_quote_in_literal() {
+        let script = "insert into t values (1, 'it\\'s;');\ninsert into t values (2, '\\\\');\n";
+        assert_eq!(
+            statements(script).unwrap(),
+            [
+                "insert into t values (1, 'it\\'s;')",
+                "insert into t values (2, '\\\\')"
+            ]
+        );
+    }
+
+    #[test]
+    fn comment_line_in_statement() {
+        let script = "insert into t values\n-- it's a comment;\n(1)\n-- another\n;\n";
+        assert_eq!(statements(script).unwrap(), ["insert into t values\n(1)"]);
+        // but not in a literal
+        let script = "insert into t values (1, 'a\n-- b');\n";
+        assert_eq!(
+            statements(script).unwrap(),
+            ["insert into t values (1, 'a\n-- b')"]
+        );
+    }
+
+    #[test]
+    fn first_values_of_rows() {
+        assert_eq!(
+            first_values("insert into t values (1, 'a(b'), (2, 'c)'),(3,'d\\')(')"),
+            ["1", "2", "3"]
+        );
+        assert_eq!(
+            first_values("insert into t values ( 4 ,'x, y') (5, NULL)"),
+            ["4", "5"]
+        );
+    }
+
+    #[test]
+    fn floats_without_literal() {
+        let path = std::env::temp_dir().join(format!("floats-{}.sql", std::process::id()));
+        for value in [Field::Float(f32::NAN), Field::Double(f64::NEG_INFINITY)] {
+            let rows = vec![vec![
+                Field::Timestamp(libtaos::Timestamp::new(
+                    1,
+                    libtaos::TimestampPrecision::Milli,
+                )),
+                value,
+            ]];
+            let result = sql_dumpout(
+                &path,
+                "t",
+                &["ts", "v"].map(String::from),
+                &["TIMESTAMP", "DOUBLE"].map(BString::from),
+                &[],
+                vec![Ok(rows)].into_iter(),
+            );
+            assert!(result.is_err());
+        }
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,281 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use utils::error::{Error, Result};
+
+/// Read a JSON file of the dump directory, `None` when it does not exist.
+pub(crate) fn read_json<T: DeserializeOwned>(path: &Path) -> Result<Option<T>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let content = fs::read_to_string(path).map_err(|err| Error::io(path, err))?;
+    serde_json::from_str(&content)
+        .map(Some)
+        .map_err(|err| Error::decode(path, err))
+}
+
+/// Write a JSON file of the dump directory.
+pub(crate) fn write_json<T: Serialize>(path: &Path, value: &T) -> Result<()> {
+    let content = serde_json::to_string_pretty(value).map_err(|err| Error::encode(path, err))?;
+    // write aside and rename, so that a crash never leaves a truncated file behind
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    let tmp_path = PathBuf::from(tmp_path);
+    fs::File::create(&tmp_path)
+        .and_then(|mut file| {
+            file.write_all(content.as_bytes())?;
+            file.sync_all()
+        })
+        .map_err(|err| Error::io(&tmp_path, err))?;
+    fs::rename(&tmp_path, path).map_err(|err| Error::io(path, err))
+}
+
+/// An append-only file of JSON records, one per line, so that recording the progress of a run
+/// costs the size of a record rather than of everything recorded so far.
+#[derive(Debug)]
+pub(crate) struct Journal {
+    path: PathBuf,
+    file: fs::File,
+}
+
+impl Journal {
+    /// Open the journal at `path` for appending, with the records it holds when `keep`, or
+    /// emptied otherwise.
+    pub(crate) fn open<T: DeserializeOwned>(path: PathBuf, keep: bool) -> Result<(Self, Vec<T>)> {
+        let (records, length) = match keep {
+            true => read_journal(&path)?,
+            false => (vec![], 0),
+        };
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .map_err(|err| Error::io(&path, err))?;
+        // drop a record cut by a crash, so that the next one starts on a line of its own
+        file.set_len(length).map_err(|err| Error::io(&path, err))?;
+        Ok((Journal { path, file }, records))
+    }
+
+    /// Drop every record, once they are saved elsewhere.
+    pub(crate) fn clear(&mut self) -> Result<()> {
+        self.file
+            .set_len(0)
+            .map_err(|err| Error::io(&self.path, err))
+    }
+
+    /// Append `record`, which is on disk once this returns.
+    pub(crate) fn append<T: Serialize>(&mut self, record: &T) -> Result<()> {
+        let mut line = serde_json::to_vec(record).map_err(|err| Error::encode(&self.path, err))?;
+        line.push(b'\n');
+        self.file
+            .write_all(&line)
+            .and_then(|_| self.file.sync_data())
+            .map_err(|err| Error::io(&self.path, err))
+    }
+}
+
+/// Records of the journal at `path`, none when it does not exist, and the length of the lines
+/// they were read from: a last line cut by a crash is left out.
+pub(crate) fn read_journal<T: DeserializeOwned>(path: &Path) -> Result<(Vec<T>, u64)> {
+    if !path.exists() {
+        return Ok((vec![], 0));
+    }
+    let content = fs::read(path).map_err(|err| Error::io(path, err))?;
+    let mut records = vec![];
+    let mut length = 0;
+    for line in content.split_inclusive(|&byte| byte == b'\n') {
+        if !line.ends_with(b"\n") {
+            break;
+        }
+        records.push(serde_json::from_slice(line).map_err(|err| Error::decode(path, err))?);
+        length += line.len() as u64;
+    }
+    Ok((records, length))
+}
+
+/// Timestamp of the last row dumped out of each table by incremental dumps, kept in
+/// `<database>.state` of the dump directory.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct HighWaterMarks {
+    tables: BTreeMap<String, i64>,
+}
+
+impl HighWaterMarks {
+    pub fn path(dir_path: &str, name: &str) -> PathBuf {
+        Path::new(dir_path).join(format!("{}.state", name))
+    }
+
+    /// Load the marks of database `name`, or none when it was never dumped incrementally.
+    pub fn load(dir_path: &str, name: &str) -> Result<Self> {
+        Ok(read_json(&Self::path(dir_path, name))?.unwrap_or_default())
+    }
+
+    pub fn save(&self, dir_path: &str, name: &str) -> Result<()> {
+        write_json(&Self::path(dir_path, name), self)
+    }
+
+    pub fn get(&self, table: &str) -> Option<i64> {
+        self.tables.get(table).copied()
+    }
+
+    pub fn set(&mut self, table: &str, ts: i64) {
+        self.tables.insert(table.to_string(), ts);
+    }
+}
+
+/// Progress of one data file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileStatus {
+    pub table: String,
+    pub done: bool,
+}
+
+/// Status of a data file as recorded in the checkpoint journal.
+#[derive(Debug, Serialize, Deserialize)]
+struct FileRecord {
+    file: String,
+    #[serde(flatten)]
+    status: FileStatus,
+}
+
+/// Completion status of every data file of a dumpout or dumpin run, journaled as each file
+/// starts and finishes so that `--resume` can skip the finished ones after a crash.
+///
+/// A file that was started but not finished is processed again from the beginning, which is
+/// safe: dumpout rewrites the file and TDengine ignores rows loaded twice, as the timestamp is
+/// the primary key.
+#[derive(Debug)]
+pub struct Checkpoint {
+    journal: Journal,
+    /// Status by data file name.
+    files: BTreeMap<String, FileStatus>,
+}
+
+impl Checkpoint {
+    /// Path of the checkpoint of `command` ("dumpout" or "dumpin") on database `name`.
+    pub fn path(dir_path: &str, name: &str, command: &str) -> PathBuf {
+        Path::new(dir_path).join(format!("{}.{}.checkpoint", name, command))
+    }
+
+    /// Resume the checkpoint at `path`, or start a new one when `resume` is off or there is
+    /// nothing to resume.
+    pub fn open(path: PathBuf, resume: bool) -> Result<Self> {
+        let (journal, records) = Journal::open::<FileRecord>(path, resume)?;
+        // later records of a file supersede earlier ones
+        let files = records
+            .into_iter()
+            .map(|record| (record.file, record.status))
+            .collect();
+        Ok(Checkpoint { journal, files })
+    }
+
+    /// Whether `file` was completely written or loaded.
+    pub fn is_done(&self, file: &Path) -> bool {
+        self.files
+            .get(&file_name(file))
+            .is_some_and(|status| status.done)
+    }
+
+    /// Whether every file of `table` is done, and there is at least one.
+    pub fn is_table_done(&self, table: &str) -> bool {
+        let mut files = self
+            .files
+            .values()
+            .filter(|status| status.table == table)
+            .peekable();
+        files.peek().is_some() && files.all(|status| status.done)
+    }
+
+    pub fn start(&mut self, file: &Path, table: &str) -> Result<()> {
+        let status = FileStatus {
+            table: table.to_string(),
+            done: false,
+        };
+        self.record(file_name(file), status)
+    }
+
+    pub fn finish(&mut self, file: &Path) -> Result<()> {
+        let file = file_name(file);
+        match self.files.get(&file) {
+            Some(status) => {
+                let status = FileStatus {
+                    done: true,
+                    ..status.clone()
+                };
+                self.record(file, status)
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Forget every file once the run completed, so that the next `--resume` starts over
+    /// rather than skipping what this run did.
+    pub fn clear(&mut self) -> Result<()> {
+        self.journal.clear()?;
+        self.files.clear();
+        Ok(())
+    }
+
+    fn record(&mut self, file: String, status: FileStatus) -> Result<()> {
+        let record = FileRecord { file, status };
+        self.journal.append(&record)?;
+        self.files.insert(record.file, record.status);
+        Ok(())
+    }
+}
+
+/// Files are recorded by name, so that the dump directory can be moved.
+pub(crate) fn file_name(file: &Path) -> String {
+    file.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("state-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn checkpoint_resumes_an_interrupted_run() {
+        let dir = temp_dir("interrupted");
+        let path = dir.join("db.dumpin.checkpoint");
+        let (first, second) = (Path::new("t1.csv"), Path::new("t2.csv"));
+        let mut checkpoint = Checkpoint::open(path.clone(), false).unwrap();
+        checkpoint.start(first, "t1").unwrap();
+        checkpoint.finish(first).unwrap();
+        checkpoint.start(second, "t2").unwrap();
+
+        let checkpoint = Checkpoint::open(path, true).unwrap();
+        assert!(checkpoint.is_done(first));
+        assert!(checkpoint.is_table_done("t1"));
+        assert!(!checkpoint.is_done(second));
+        assert!(!checkpoint.is_table_done("t2"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn checkpoint_resumes_nothing_after_a_complete_run() {
+        let dir = temp_dir("complete");
+        let path = dir.join("db.dumpout.checkpoint");
+        let file = Path::new("t1.csv");
+        let mut checkpoint = Checkpoint::open(path.clone(), false).unwrap();
+        checkpoint.start(file, "t1").unwrap();
+        checkpoint.finish(file).unwrap();
+        checkpoint.clear().unwrap();
+
+        let checkpoint = Checkpoint::open(path, true).unwrap();
+        assert!(!checkpoint.is_done(file));
+        assert!(!checkpoint.is_table_done("t1"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,297 @@
+use std::str::FromStr;
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use libtaos::{Taos, Timestamp, TimestampPrecision};
+use utils::error::{Error, Result};
+
+/// Number of timestamp units per second in `precision`.
+pub fn units_per_second(precision: TimestampPrecision) -> i64 {
+    match precision {
+        TimestampPrecision::Micro => 1_000_000,
+        TimestampPrecision::Nano => 1_000_000_000,
+        _ => 1_000,
+    }
+}
+
+/// `epoch` in precision `from` as an epoch in precision `to`, rounded down.
+pub fn convert_epoch(epoch: i64, from: TimestampPrecision, to: TimestampPrecision) -> i64 {
+    (epoch as i128 * units_per_second(to) as i128).div_euclid(units_per_second(from) as i128) as i64
+}
+
+/// `timestamp` as an RFC3339 time in UTC, with as many decimals as `precision` has.
+pub fn to_rfc3339(timestamp: &Timestamp, precision: TimestampPrecision) -> String {
+    let digits = match precision {
+        TimestampPrecision::Micro => SecondsFormat::Micros,
+        TimestampPrecision::Nano => SecondsFormat::Nanos,
+        _ => SecondsFormat::Millis,
+    };
+    DateTime::<Utc>::from_utc(timestamp.to_naive_datetime(), Utc).to_rfc3339_opts(digits, true)
+}
+
+/// Timestamp precision of database `name`, as listed by `show databases`.
+pub fn database_precision(taos: &Taos, name: &str) -> Result<TimestampPrecision> {
+    let sql = "show databases";
+    let rows = taos
+        .raw_query(sql)
+        .map_err(|err| Error::query(sql, err))?
+        .fetch_fields();
+    let index = rows
+        .column_meta
+        .iter()
+        .position(|meta| meta.name == "precision")
+        .ok_or_else(|| Error::schema(name, format!("no precision column in {}", sql)))?;
+    let row = rows
+        .rows
+        .iter()
+        .find(|row| row[0].to_string() == name)
+        .ok_or_else(|| Error::schema(name, format!("database not found by {}", sql)))?;
+    match row[index].to_string().as_str() {
+        "ms" => Ok(TimestampPrecision::Milli),
+        "us" => Ok(TimestampPrecision::Micro),
+        "ns" => Ok(TimestampPrecision::Nano),
+        precision => Err(Error::schema(
+            name,
+            format!("unknown precision {} in {}", precision, sql),
+        )),
+    }
+}
+
+/// Timestamp precision given by the `PRECISION '<unit>'` option of a `CREATE DATABASE` DDL.
+pub fn ddl_precision(ddl: &str) -> Option<TimestampPrecision> {
+    let mut words = ddl.split_whitespace();
+    words.find(|word| word.eq_ignore_ascii_case("precision"))?;
+    match words.next()?.trim_matches(|c| c == '\'' || c == '"') {
+        "ms" => Some(TimestampPrecision::Milli),
+        "us" => Some(TimestampPrecision::Micro),
+        "ns" => Some(TimestampPrecision::Nano),
+        _ => None,
+    }
+}
+
+/// A point in time given on the command line, either an RFC3339 time such as
+/// `2022-03-01T00:00:00+08:00`, an epoch with a unit such as `1646064000s` or `1646064000000ms`,
+/// or a bare epoch in the precision of the database.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeBound {
+    /// Nanoseconds since the epoch.
+    Nanos(i64),
+    /// Epoch in the precision of the database.
+    Raw(i64),
+}
+
+impl TimeBound {
+    /// The bound as an epoch in `precision`, rounded down as [`convert_epoch`] does.
+    pub fn to_epoch(self, precision: TimestampPrecision) -> i64 {
+        match self {
+            TimeBound::Nanos(nanos) => {
+                nanos.div_euclid(1_000_000_000 / units_per_second(precision))
+            }
+            TimeBound::Raw(epoch) => epoch,
+        }
+    }
+
+    /// The first epoch in `precision` at or after the bound.
+    pub fn to_epoch_ceil(self, precision: TimestampPrecision) -> i64 {
+        match self {
+            TimeBound::Nanos(nanos) => {
+                let units = 1_000_000_000 / units_per_second(precision);
+                match nanos.rem_euclid(units) {
+                    0 => nanos.div_euclid(units),
+                    _ => nanos.div_euclid(units) + 1,
+                }
+            }
+            TimeBound::Raw(epoch) => epoch,
+        }
+    }
+
+    /// Epoch in `precision` of a timestamp read from a data file, where a bare epoch is in
+    /// `source`, the precision of the dumped database.
+    pub fn to_epoch_from(self, source: TimestampPrecision, precision: TimestampPrecision) -> i64 {
+        match self {
+            TimeBound::Raw(epoch) => convert_epoch(epoch, source, precision),
+            bound => bound.to_epoch(precision),
+        }
+    }
+}
+
+impl FromStr for TimeBound {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        if let Ok(epoch) = s.parse::<i64>() {
+            return Ok(TimeBound::Raw(epoch));
+        }
+        // longest suffix first, so that `ms` is not taken for `s`
+        for (unit, nanos) in [
+            ("ns", 1),
+            ("us", 1_000),
+            ("ms", 1_000_000),
+            ("s", 1_000_000_000),
+        ] {
+            if let Some(epoch) = s.strip_suffix(unit).and_then(|v| v.parse::<i64>().ok()) {
+                return epoch
+                    .checked_mul(nanos)
+                    .map(TimeBound::Nanos)
+                    .ok_or_else(|| format!("{} is out of range", s));
+            }
+        }
+        let time = DateTime::parse_from_rfc3339(s)
+            .map_err(|err| format!("{} is neither an epoch nor an RFC3339 time: {}", s, err))?;
+        // nanoseconds since the epoch only fit in an i64 between 1677 and 2262
+        time.timestamp()
+            .checked_mul(1_000_000_000)
+            .and_then(|nanos| nanos.checked_add(time.timestamp_subsec_nanos() as i64))
+            .map(TimeBound::Nanos)
+            .ok_or_else(|| format!("{} is out of range", s))
+    }
+}
+
+/// Time range of the rows to dump, from `start` inclusive to `end` exclusive, so that
+/// consecutive ranges never dump a row twice.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TimeRange {
+    pub start: Option<TimeBound>,
+    pub end: Option<TimeBound>,
+}
+
+impl TimeRange {
+    /// SQL conditions on `ts_column` that select the range.
+    pub fn conditions(&self, ts_column: &str, precision: TimestampPrecision) -> Vec<String> {
+        // for a timestamp in whole units, `start <= ts` is `ceil(start) <= ts` and `ts < end`
+        // is `ts < ceil(end)`, so both bounds round up
+        let mut conditions = vec![];
+        if let Some(start) = self.start {
+            conditions.push(format!(
+                "{} >= {}",
+                ts_column,
+                start.to_epoch_ceil(precision)
+            ));
+        }
+        if let Some(end) = self.end {
+            conditions.push(format!("{} < {}", ts_column, end.to_epoch_ceil(precision)));
+        }
+        conditions
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bare_epoch_is_raw() {
+        assert_eq!("1646064000000".parse(), Ok(TimeBound::Raw(1646064000000)));
+        assert_eq!("-1".parse(), Ok(TimeBound::Raw(-1)));
+    }
+
+    #[test]
+    fn epoch_with_unit() {
+        let nanos = 1646064000 * 1_000_000_000;
+        assert_eq!("1646064000s".parse(), Ok(TimeBound::Nanos(nanos)));
+        assert_eq!("1646064000000ms".parse(), Ok(TimeBound::Nanos(nanos)));
+        assert_eq!("1646064000000000us".parse(), Ok(TimeBound::Nanos(nanos)));
+        assert_eq!("1646064000000000000ns".parse(), Ok(TimeBound::Nanos(nanos)));
+    }
+
+    #[test]
+    fn rfc3339_time() {
+        let nanos = 1646064000 * 1_000_000_000;
+        assert_eq!(
+            "2022-03-01T00:00:00+08:00".parse(),
+            Ok(TimeBound::Nanos(nanos))
+        );
+        assert_eq!(
+            "2022-02-28T16:00:00.000000001Z".parse(),
+            Ok(TimeBound::Nanos(nanos + 1))
+        );
+        assert_eq!(
+            "1969-12-31T23:59:59.5Z".parse(),
+            Ok(TimeBound::Nanos(-500_000_000))
+        );
+    }
+
+    #[test]
+    fn overflowing_bounds() {
+        assert!("9223372037s".parse::<TimeBound>().is_err());
+        assert!("9223372036855ms".parse::<TimeBound>().is_err());
+        assert!("2263-01-01T00:00:00Z".parse::<TimeBound>().is_err());
+        assert!("1677-01-01T00:00:00Z".parse::<TimeBound>().is_err());
+        assert!("2262-04-11T23:47:16Z".parse::<TimeBound>().is_ok());
+    }
+
+    #[test]
+    fn malformed_bounds() {
+        for bound in [
+            "",
+            "s",
+            "12x",
+            "1.5s",
+            "ms10",
+            "2022-03-01",
+            "2022-13-01T00:00:00Z",
+        ] {
+            assert!(bound.parse::<TimeBound>().is_err(), "{}", bound);
+        }
+    }
+
+    #[test]
+    fn bound_to_epoch() {
+        let bound = TimeBound::Nanos(1_646_064_000_123_456_789);
+        assert_eq!(bound.to_epoch(TimestampPrecision::Milli), 1_646_064_000_123);
+        assert_eq!(
+            bound.to_epoch(TimestampPrecision::Micro),
+            1_646_064_000_123_456
+        );
+        let raw = TimeBound::Raw(1_646_064_000_123);
+        assert_eq!(
+            raw.to_epoch_from(TimestampPrecision::Milli, TimestampPrecision::Nano),
+            1_646_064_000_123_000_000
+        );
+    }
+
+    #[test]
+    fn negative_bound_to_epoch() {
+        let bound: TimeBound = "1969-12-31T23:59:59.9995Z".parse().unwrap();
+        assert_eq!(bound.to_epoch(TimestampPrecision::Milli), -1);
+        assert_eq!(bound.to_epoch_ceil(TimestampPrecision::Milli), 0);
+        let bound = TimeBound::Nanos(-1_500_000);
+        assert_eq!(bound.to_epoch(TimestampPrecision::Milli), -2);
+        assert_eq!(bound.to_epoch_ceil(TimestampPrecision::Milli), -1);
+        let bound = TimeBound::Nanos(-2_000_000);
+        assert_eq!(bound.to_epoch(TimestampPrecision::Milli), -2);
+        assert_eq!(bound.to_epoch_ceil(TimestampPrecision::Milli), -2);
+    }
+
+    fn range_conditions(start: &str, end: &str, precision: TimestampPrecision) -> Vec<String> {
+        let range = TimeRange {
+            start: Some(start.parse().unwrap()),
+            end: Some(end.parse().unwrap()),
+        };
+        range.conditions("ts", precision)
+    }
+
+    #[test]
+    fn sub_precision_bounds() {
+        // rows at 0 ms are before the start and within the end, those at 1 ms the other way
+        assert_eq!(
+            range_conditions(
+                "1970-01-01T00:00:00.0005Z",
+                "1970-01-01T00:00:00.0015Z",
+                TimestampPrecision::Milli
+            ),
+            ["ts >= 1", "ts < 2"]
+        );
+        assert_eq!(
+            range_conditions("1000us", "2000us", TimestampPrecision::Milli),
+            ["ts >= 1", "ts < 2"]
+        );
+        assert_eq!(
+            range_conditions("-1500us", "-500us", TimestampPrecision::Milli),
+            ["ts >= -1", "ts < 0"]
+        );
+        assert_eq!(
+            range_conditions("1", "2", TimestampPrecision::Nano),
+            ["ts >= 1", "ts < 2"]
+        );
+    }
+}
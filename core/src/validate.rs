@@ -0,0 +1,171 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use libtaos::{Field, Taos};
+use log::warn;
+use utils::error::{Error, Result};
+
+use crate::dumpin::source_precision;
+use crate::manifest::{numeric_value, ColumnAggregate, Manifest, Summary};
+use crate::state::file_name;
+use crate::time::{convert_epoch, database_precision};
+
+/// Relative difference tolerated between two sums of a column, as TDengine adds the values up
+/// in another order.
+const SUM_TOLERANCE: f64 = 1e-6;
+
+/// What a restored table should hold, all of its data files together.
+#[derive(Debug, Default)]
+struct Expected {
+    summary: Summary,
+    columns: BTreeMap<String, ColumnAggregate>,
+}
+
+/// Compare every table restored from `file_list` into database `name` with what its data files
+/// held at dumpout, as recorded in the manifest of database `source`, and report each table that
+/// does not match.
+pub fn validate(
+    taos: &Taos,
+    dir_path: &str,
+    source: &str,
+    name: &str,
+    file_list: &[PathBuf],
+) -> Result<()> {
+    let manifest = Manifest::load(dir_path, source)?;
+    let mut tables: BTreeMap<&str, Expected> = BTreeMap::new();
+    for file in file_list {
+        let entry = match manifest.files.get(&file_name(file)) {
+            Some(entry) => entry,
+            None => {
+                warn!("{} is not in the manifest, not validated", file.display());
+                continue;
+            }
+        };
+        let expected = tables.entry(entry.table.as_str()).or_default();
+        expected.summary.merge(&entry.summary);
+        for (column, aggregate) in &entry.columns {
+            expected
+                .columns
+                .entry(column.clone())
+                .or_default()
+                .merge(aggregate);
+        }
+    }
+
+    // timestamps of the manifest are in the precision of the dumped database
+    let precision = database_precision(taos, name)?;
+    let source_precision = source_precision(dir_path, source)?;
+    for expected in tables.values_mut() {
+        let summary = &mut expected.summary;
+        summary.min_ts = summary
+            .min_ts
+            .map(|ts| convert_epoch(ts, source_precision, precision));
+        summary.max_ts = summary
+            .max_ts
+            .map(|ts| convert_epoch(ts, source_precision, precision));
+    }
+
+    let mut failures = 0;
+    for (table, expected) in &tables {
+        match validate_table(taos, name, table, expected) {
+            Ok(problems) if problems.is_empty() => println!("{}: ok", table),
+            Ok(problems) => {
+                failures += 1;
+                println!("{}: {}", table, problems.join(", "));
+            }
+            Err(err) => {
+                failures += 1;
+                println!("{}: {}", table, err);
+            }
+        }
+    }
+
+    if failures > 0 {
+        return Err(Error::Verify(format!(
+            "{} of the {} restored tables of database {} do not match the dump",
+            failures,
+            tables.len(),
+            name
+        )));
+    }
+    Ok(())
+}
+
+/// Differences between a restored table and what was dumped out of it.
+fn validate_table(
+    taos: &Taos,
+    name: &str,
+    table: &str,
+    expected: &Expected,
+) -> Result<Vec<String>> {
+    let sql = format!("describe {}.{}", name, table);
+    let rows = taos
+        .raw_query(&sql)
+        .map_err(|err| Error::query(&sql, err))?
+        .fetch_fields()
+        .rows;
+    let ts_column = match rows.first() {
+        Some(row) => row[0].to_string(),
+        None => return Err(Error::schema(table, "no columns found by describe")),
+    };
+
+    let mut aggregates = vec![
+        "count(*)".to_string(),
+        format!("first({})", ts_column),
+        format!("last({})", ts_column),
+    ];
+    for (column, aggregate) in &expected.columns {
+        aggregates.push(format!("count({})", column));
+        if aggregate.sum.is_some() {
+            aggregates.push(format!("sum({})", column));
+        }
+    }
+    let sql = format!("select {} from {}.{}", aggregates.join(","), name, table);
+    let rows = taos
+        .raw_query(&sql)
+        .map_err(|err| Error::query(&sql, err))?
+        .fetch_fields()
+        .rows;
+    // an empty table returns no row at all
+    let row = rows.into_iter().next().unwrap_or_default();
+    let mut values = row.iter();
+
+    let mut problems = vec![];
+    let rows = count(values.next());
+    if rows != expected.summary.rows {
+        problems.push(format!("{} rows, expected {}", rows, expected.summary.rows));
+    }
+    let first_ts = values.next().and_then(Field::as_raw_timestamp);
+    let last_ts = values.next().and_then(Field::as_raw_timestamp);
+    if (first_ts, last_ts) != (expected.summary.min_ts, expected.summary.max_ts) {
+        problems.push(format!(
+            "timestamps {:?}..{:?}, expected {:?}..{:?}",
+            first_ts, last_ts, expected.summary.min_ts, expected.summary.max_ts
+        ));
+    }
+    for (column, aggregate) in &expected.columns {
+        let count = count(values.next());
+        if count != aggregate.count {
+            problems.push(format!(
+                "{} values in {}, expected {}",
+                count, column, aggregate.count
+            ));
+        }
+        if let Some(expected_sum) = aggregate.sum {
+            let sum = values.next().and_then(numeric_value).unwrap_or(0.0);
+            let tolerance = SUM_TOLERANCE * sum.abs().max(expected_sum.abs());
+            if (sum - expected_sum).abs() > tolerance {
+                problems.push(format!(
+                    "sum of {} is {}, expected {}",
+                    column, sum, expected_sum
+                ));
+            }
+        }
+    }
+    Ok(problems)
+}
+
+/// Value of a `count()` aggregate, missing for an empty table.
+fn count(field: Option<&Field>) -> u64 {
+    field.and_then(numeric_value).unwrap_or(0.0) as u64
+}
@@ -0,0 +1,371 @@
+/// WKT tags of the geometry types, in the order of their WKB type codes, from 1.
+const GEOMETRY_TYPES: [&str; 7] = [
+    "POINT",
+    "LINESTRING",
+    "POLYGON",
+    "MULTIPOINT",
+    "MULTILINESTRING",
+    "MULTIPOLYGON",
+    "GEOMETRYCOLLECTION",
+];
+
+/// Dimension tags of WKT, in the order of the thousands of their ISO WKB type codes.
+const DIMENSIONS: [&str; 4] = ["", "Z", "M", "ZM"];
+
+/// WKB bytes, little-endian, of the geometry written as WKT text in `wkt`, such as the
+/// `st_astext()` of a GEOMETRY value, `None` when it is not WKT.
+pub fn wkt_to_wkb(wkt: &str) -> Option<Vec<u8>> {
+    let mut parser = WktParser {
+        tokens: tokens(wkt),
+        next: 0,
+        wkb: vec![],
+    };
+    parser.geometry()?;
+    match parser.next == parser.tokens.len() {
+        true => Some(parser.wkb),
+        false => None,
+    }
+}
+
+/// WKT text of the geometry in WKB bytes `wkb`, which TDengine takes as a GEOMETRY value,
+/// `None` when they are not WKB.
+pub fn wkb_to_wkt(wkb: &[u8]) -> Option<String> {
+    let mut reader = WkbReader { wkb, little: true };
+    let mut wkt = String::new();
+    reader.geometry(&mut wkt, true)?;
+    match reader.wkb.is_empty() {
+        true => Some(wkt),
+        false => None,
+    }
+}
+
+/// Words, numbers and punctuation of WKT text.
+fn tokens(wkt: &str) -> Vec<&str> {
+    let mut tokens = vec![];
+    let mut start = None;
+    for (index, c) in wkt.char_indices() {
+        if c.is_whitespace() || matches!(c, '(' | ')' | ',') {
+            if let Some(start) = start.take() {
+                tokens.push(&wkt[start..index]);
+            }
+            if !c.is_whitespace() {
+                tokens.push(&wkt[index..index + 1]);
+            }
+        } else if start.is_none() {
+            start = Some(index);
+        }
+    }
+    if let Some(start) = start {
+        tokens.push(&wkt[start..]);
+    }
+    tokens
+}
+
+struct WktParser<'a> {
+    tokens: Vec<&'a str>,
+    next: usize,
+    wkb: Vec<u8>,
+}
+
+impl WktParser<'_> {
+    fn peek_is(&self, token: &str) -> bool {
+        self.tokens
+            .get(self.next)
+            .is_some_and(|next| next.eq_ignore_ascii_case(token))
+    }
+
+    fn expect(&mut self, token: &str) -> Option<()> {
+        match self.peek_is(token) {
+            true => {
+                self.next += 1;
+                Some(())
+            }
+            false => None,
+        }
+    }
+
+    fn header(&mut self, kind: usize, dimension: usize) {
+        self.wkb.push(1);
+        let code = (dimension * 1000 + kind) as u32;
+        self.wkb.extend_from_slice(&code.to_le_bytes());
+    }
+
+    /// A tagged geometry.
+    fn geometry(&mut self) -> Option<()> {
+        let tag = self.tokens.get(self.next)?;
+        let kind = GEOMETRY_TYPES
+            .iter()
+            .position(|name| name.eq_ignore_ascii_case(tag))?
+            + 1;
+        self.next += 1;
+        let dimension = (1..DIMENSIONS.len())
+            .find(|&dimension| self.peek_is(DIMENSIONS[dimension]))
+            .unwrap_or(0);
+        if dimension != 0 {
+            self.next += 1;
+        }
+        self.header(kind, dimension);
+        self.body(kind, dimension)
+    }
+
+    /// The coordinates of a geometry of type `kind`, following its tag or within a multi one.
+    fn body(&mut self, kind: usize, dimension: usize) -> Option<()> {
+        if self.expect("EMPTY").is_some() {
+            match kind {
+                1 => self.empty_point(dimension),
+                _ => self.wkb.extend_from_slice(&0u32.to_le_bytes()),
+            }
+            return Some(());
+        }
+        self.expect("(")?;
+        match kind {
+            1 => self.point(dimension)?,
+            2 => self.list(|parser| parser.point(dimension))?,
+            3 => self.list(|parser| parser.body(2, dimension))?,
+            4 => self.list(|parser| {
+                parser.header(1, dimension);
+                // the points of a multipoint come with or without parentheses
+                match parser.peek_is("(") || parser.peek_is("EMPTY") {
+                    true => parser.body(1, dimension),
+                    false => parser.point(dimension),
+                }
+            })?,
+            5 | 6 => self.list(|parser| {
+                parser.header(kind - 3, dimension);
+                parser.body(kind - 3, dimension)
+            })?,
+            _ => self.list(|parser| parser.geometry())?,
+        }
+        self.expect(")")
+    }
+
+    /// Elements separated by commas, after their count.
+    fn list(&mut self, mut element: impl FnMut(&mut Self) -> Option<()>) -> Option<()> {
+        let at = self.wkb.len();
+        self.wkb.extend_from_slice(&0u32.to_le_bytes());
+        let mut count = 0u32;
+        loop {
+            element(self)?;
+            count += 1;
+            if self.expect(",").is_none() {
+                break;
+            }
+        }
+        self.wkb[at..at + 4].copy_from_slice(&count.to_le_bytes());
+        Some(())
+    }
+
+    fn point(&mut self, dimension: usize) -> Option<()> {
+        for _ in 0..coordinates(dimension) {
+            let value: f64 = self.tokens.get(self.next)?.parse().ok()?;
+            self.next += 1;
+            self.wkb.extend_from_slice(&value.to_le_bytes());
+        }
+        Some(())
+    }
+
+    /// WKB has no empty point, which is written with NaN coordinates.
+    fn empty_point(&mut self, dimension: usize) {
+        for _ in 0..coordinates(dimension) {
+            self.wkb.extend_from_slice(&f64::NAN.to_le_bytes());
+        }
+    }
+}
+
+/// Number of coordinates of a point of `dimension`.
+fn coordinates(dimension: usize) -> usize {
+    match dimension {
+        0 => 2,
+        3 => 4,
+        _ => 3,
+    }
+}
+
+struct WkbReader<'a> {
+    wkb: &'a [u8],
+    /// Byte order of the geometry being read.
+    little: bool,
+}
+
+impl WkbReader<'_> {
+    fn take<const N: usize>(&mut self) -> Option<[u8; N]> {
+        let (bytes, rest) = (self.wkb.get(..N)?, &self.wkb[N..]);
+        self.wkb = rest;
+        bytes.try_into().ok()
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        let bytes = self.take()?;
+        Some(match self.little {
+            true => u32::from_le_bytes(bytes),
+            false => u32::from_be_bytes(bytes),
+        })
+    }
+
+    fn f64(&mut self) -> Option<f64> {
+        let bytes = self.take()?;
+        Some(match self.little {
+            true => f64::from_le_bytes(bytes),
+            false => f64::from_be_bytes(bytes),
+        })
+    }
+
+    /// Read a geometry into `wkt`, with its tag when `tagged`, and return its type and
+    /// dimension.
+    fn geometry(&mut self, wkt: &mut String, tagged: bool) -> Option<(usize, usize)> {
+        self.little = match self.take::<1>()? {
+            [0] => false,
+            [1] => true,
+            _ => return None,
+        };
+        let code = self.u32()?;
+        // the Z and M flags of extended WKB, as GEOS writes it, or the thousands of ISO WKB
+        let dimension = match (code & 0x8000_0000 != 0, code & 0x4000_0000 != 0) {
+            (false, false) => ((code & 0xffff) / 1000) as usize,
+            (true, false) => 1,
+            (false, true) => 2,
+            (true, true) => 3,
+        };
+        let kind = (code & 0x0fff_ffff) as usize % 1000;
+        if code & 0x2000_0000 != 0 || dimension >= DIMENSIONS.len() {
+            return None;
+        }
+        if tagged {
+            wkt.push_str(GEOMETRY_TYPES.get(kind.checked_sub(1)?)?);
+            wkt.push(' ');
+            if dimension != 0 {
+                wkt.push_str(DIMENSIONS[dimension]);
+                wkt.push(' ');
+            }
+        }
+        match kind {
+            1 => {
+                let point = (0..coordinates(dimension))
+                    .map(|_| self.f64())
+                    .collect::<Option<Vec<_>>>()?;
+                match point.iter().all(|value| value.is_nan()) {
+                    true => wkt.push_str("EMPTY"),
+                    false => {
+                        wkt.push('(');
+                        push_point(wkt, &point);
+                        wkt.push(')');
+                    }
+                }
+            }
+            2 => self.list(wkt, |reader, wkt| reader.point(wkt, dimension))?,
+            3 => self.list(wkt, |reader, wkt| {
+                reader.list(wkt, |reader, wkt| reader.point(wkt, dimension))
+            })?,
+            4..=6 => self.list(wkt, |reader, wkt| match reader.geometry(wkt, false)? {
+                (element, _) if element == kind - 3 => Some(()),
+                _ => None,
+            })?,
+            7 => self.list(wkt, |reader, wkt| reader.geometry(wkt, true).map(|_| ()))?,
+            _ => return None,
+        }
+        Some((kind, dimension))
+    }
+
+    /// Elements after their count, in parentheses and separated by commas.
+    fn list(
+        &mut self,
+        wkt: &mut String,
+        mut element: impl FnMut(&mut Self, &mut String) -> Option<()>,
+    ) -> Option<()> {
+        let count = self.u32()?;
+        if count == 0 {
+            wkt.push_str("EMPTY");
+            return Some(());
+        }
+        wkt.push('(');
+        for index in 0..count {
+            if index != 0 {
+                wkt.push_str(", ");
+            }
+            element(self, wkt)?;
+        }
+        wkt.push(')');
+        Some(())
+    }
+
+    fn point(&mut self, wkt: &mut String, dimension: usize) -> Option<()> {
+        let point = (0..coordinates(dimension))
+            .map(|_| self.f64())
+            .collect::<Option<Vec<_>>>()?;
+        push_point(wkt, &point);
+        Some(())
+    }
+}
+
+fn push_point(wkt: &mut String, point: &[f64]) {
+    let values = point.iter().map(f64::to_string).collect::<Vec<_>>();
+    wkt.push_str(&values.join(" "));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn point_bytes() {
+        let wkb = wkt_to_wkb("POINT (1.5 -2)").unwrap();
+        let mut expected = vec![1, 1, 0, 0, 0];
+        expected.extend_from_slice(&1.5f64.to_le_bytes());
+        expected.extend_from_slice(&(-2f64).to_le_bytes());
+        assert_eq!(wkb, expected);
+        assert_eq!(wkb_to_wkt(&wkb).unwrap(), "POINT (1.5 -2)");
+    }
+
+    #[test]
+    fn geometries_round_trip() {
+        for wkt in [
+            "POINT EMPTY",
+            "POINT Z (1 2 3)",
+            "LINESTRING (0 0, 1 1, 2 0.25)",
+            "LINESTRING EMPTY",
+            "POLYGON ((0 0, 4 0, 4 4, 0 0), (1 1, 2 1, 2 2, 1 1))",
+            "MULTIPOINT ((0 0), (1 2))",
+            "MULTILINESTRING ((0 0, 1 1), (2 2, 3 3))",
+            "MULTIPOLYGON (((0 0, 1 0, 1 1, 0 0)), ((5 5, 6 5, 6 6, 5 5)))",
+            "GEOMETRYCOLLECTION (POINT (1 2), LINESTRING M (0 0 1, 1 1 2))",
+        ] {
+            let wkb = wkt_to_wkb(wkt).unwrap();
+            assert_eq!(wkb_to_wkt(&wkb).unwrap(), wkt);
+        }
+    }
+
+    #[test]
+    fn multipoint_without_parentheses() {
+        assert_eq!(
+            wkt_to_wkb("multipoint (0 0, 1 2)"),
+            wkt_to_wkb("MULTIPOINT ((0 0), (1 2))")
+        );
+    }
+
+    #[test]
+    fn big_endian_and_extended_wkb() {
+        let mut wkb = vec![0, 0x80, 0, 0, 1];
+        for value in [1f64, 2., 3.] {
+            wkb.extend_from_slice(&value.to_be_bytes());
+        }
+        assert_eq!(wkb_to_wkt(&wkb).unwrap(), "POINT Z (1 2 3)");
+    }
+
+    #[test]
+    fn invalid_geometries() {
+        for wkt in [
+            "",
+            "POINT",
+            "POINT (1)",
+            "POINT (1 2",
+            "CIRCLE (1 2)",
+            "POINT (1 2) x",
+        ] {
+            assert_eq!(wkt_to_wkb(wkt), None, "{}", wkt);
+        }
+        let wkb = wkt_to_wkb("LINESTRING (0 0, 1 1)").unwrap();
+        assert_eq!(wkb_to_wkt(&wkb[..wkb.len() - 1]), None);
+        assert_eq!(wkb_to_wkt(&[wkb.as_slice(), &[0]].concat()), None);
+        assert_eq!(wkb_to_wkt(&[2, 1, 0, 0, 0]), None);
+    }
+}
@@ -1,11 +1,12 @@
-use utils::error::Result;
+use std::process::exit;
 extern crate log;
 extern crate pretty_env_logger;
 /// The main entry point of the application.
-fn main() -> Result<()> {
+fn main() {
     pretty_env_logger::init();
     // Match Commands
-    cli::cli_match()?;
-
-    Ok(())
+    if let Err(err) = cli::cli_match() {
+        eprintln!("error: {}", err);
+        exit(1);
+    }
 }
@@ -1,82 +1,144 @@
-use std::fmt;
+use std::path::{Path, PathBuf};
 use thiserror::Error;
 
 /// Result alias
 pub type Result<T> = std::result::Result<T, Error>;
 
+/// Boxed error of an encoding library, such as parquet or avro-rs.
+pub type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
 /// Error type for this library.
 #[derive(Error, Debug)]
-pub struct Error {
-    pub msg: String,
-    source: Option<Box<dyn std::error::Error + Send + Sync>>,
-}
+pub enum Error {
+    /// The connection settings are invalid.
+    #[error("invalid connection settings: {0}")]
+    Config(String),
 
-// Implement the Display trait for our Error type.
-impl fmt::Display for Error {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", self.msg)
-    }
-}
+    /// TDengine could not be reached or refused the login.
+    #[error("cannot connect to TDengine at {addr}: {source}")]
+    Connection {
+        addr: String,
+        #[source]
+        source: libtaos::Error,
+    },
 
-// Implement Default for Error
-impl Default for Error {
-    fn default() -> Self {
-        Error {
-            msg: "".to_string(),
-            source: None,
-        }
-    }
+    /// A statement other than an INSERT failed.
+    #[error("query failed: {source}\n  sql: {sql}")]
+    Query {
+        sql: String,
+        #[source]
+        source: libtaos::Error,
+    },
+
+    /// TDengine rejected a batch of rows loaded from a data file.
+    #[error("insert into table {table} from {} rejected: {source}\n  sql: {sql}", .file.display())]
+    InsertRejected {
+        table: String,
+        file: PathBuf,
+        sql: String,
+        #[source]
+        source: libtaos::Error,
+    },
+
+    /// A table or data file schema is missing or malformed.
+    #[error("invalid schema of table {table}: {msg}")]
+    Schema { table: String, msg: String },
+
+    /// A column type that has no mapping in the dump format.
+    #[error("unsupported data type {data_type} of column {column} in table {table}")]
+    UnsupportedType {
+        table: String,
+        column: String,
+        data_type: String,
+    },
+
+    /// Reading or writing a file of the dump directory failed.
+    #[error("IO error on {}: {source}", .path.display())]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+
+    /// A data file could not be written.
+    #[error("failed to encode {}: {source}", .file.display())]
+    Encode {
+        file: PathBuf,
+        #[source]
+        source: BoxError,
+    },
+
+    /// A data file could not be read.
+    #[error("failed to decode {}: {source}", .file.display())]
+    Decode {
+        file: PathBuf,
+        #[source]
+        source: BoxError,
+    },
+
+    /// A worker thread panicked, leaving its result or the state it shared unusable.
+    #[error("worker failed: {0}")]
+    Worker(String),
+
+    /// Dumped or restored data does not match what was recorded at dumpout.
+    #[error("verification failed: {0}")]
+    Verify(String),
+
+    #[error(transparent)]
+    Clap(#[from] clap::Error),
 }
 
 impl Error {
-    /// Create a new Error instance.
-    pub fn new(msg: &str) -> Self {
-        Error {
-            msg: msg.to_string(),
-            source: None,
+    /// A failed statement, keeping the SQL that was sent.
+    pub fn query(sql: impl Into<String>, source: libtaos::Error) -> Self {
+        Error::Query {
+            sql: sql.into(),
+            source,
         }
     }
-    /// Create a new Error instance with a source error.
-    pub fn with_source(msg: &str, source: Box<dyn std::error::Error + Send + Sync>) -> Self {
-        Error {
-            msg: msg.to_string(),
-            source: Some(source),
+
+    /// A failed IO operation on `path`.
+    pub fn io(path: impl AsRef<Path>, source: std::io::Error) -> Self {
+        Error::Io {
+            path: path.as_ref().to_path_buf(),
+            source,
         }
     }
-}
 
-impl<T> From<std::sync::PoisonError<T>> for Error {
-    fn from(_err: std::sync::PoisonError<T>) -> Self {
-        Error {
-            msg: String::from("Poison Error"),
-            source: None,
+    /// A failure of the format library while writing `file`.
+    pub fn encode(file: impl AsRef<Path>, source: impl Into<BoxError>) -> Self {
+        Error::Encode {
+            file: file.as_ref().to_path_buf(),
+            source: source.into(),
         }
     }
-}
 
-impl From<std::io::Error> for Error {
-    fn from(err: std::io::Error) -> Self {
-        Error {
-            msg: String::from("IO Error"),
-            source: Some(Box::new(err)),
+    /// A failure of the format library while reading `file`.
+    pub fn decode(file: impl AsRef<Path>, source: impl Into<BoxError>) -> Self {
+        Error::Decode {
+            file: file.as_ref().to_path_buf(),
+            source: source.into(),
         }
     }
-}
 
-impl From<clap::Error> for Error {
-    fn from(err: clap::Error) -> Self {
-        Error {
-            msg: String::from("Clap Error"),
-            source: Some(Box::new(err)),
+    /// A schema problem of `table`.
+    pub fn schema(table: impl Into<String>, msg: impl Into<String>) -> Self {
+        Error::Schema {
+            table: table.into(),
+            msg: msg.into(),
         }
     }
-}
 
-impl From<libtaos::Error> for Error {
-    fn from(err: libtaos::Error) -> Self {
-        Error {
-            msg: String::from("Taos Error"),
-            source: Some(Box::new(err)),
+    /// A column of `table` whose type cannot be dumped or restored.
+    pub fn unsupported_type(
+        table: impl Into<String>,
+        column: impl Into<String>,
+        data_type: impl ToString,
+    ) -> Self {
+        Error::UnsupportedType {
+            table: table.into(),
+            column: column.into(),
+            data_type: data_type.to_string(),
         }
     }
 }
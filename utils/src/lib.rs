@@ -1,46 +1,103 @@
 pub mod error;
 pub mod taos;
 
-use std::path::PathBuf;
+use std::any::Any;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+use std::thread::ScopedJoinHandle;
+
+use error::{Error, Result};
+
 #[derive(Debug)]
 pub enum Formats {
     Avro,
     Parquet,
+    Csv,
+    Jsonl,
+    Arrow,
+    Sql,
+    LineProtocol,
 }
-use glob::glob;
-
-pub fn get_all_data_files(dir_path: &str, file_list: &mut Vec<PathBuf>, format: &Formats) {
-    let paths;
-    if dir_path.ends_with("/") {
-        match *format {
-            Formats::Avro => {
-                paths = glob(format!("{}*.avro", dir_path).as_str())
-                    .expect("Failed to read glob pattern")
-            }
-            Formats::Parquet => {
-                paths = glob(format!("{}*.parquet", dir_path).as_str())
-                    .expect("Failed to read glob pattern")
-            }
-        }
-    } else {
-        match *format {
-            Formats::Avro => {
-                paths = glob(format!("{}/*.avro", dir_path).as_str())
-                    .expect("Failed to read glob pattern")
-            }
-            Formats::Parquet => {
-                paths = glob(format!("{}/*.parquet", dir_path).as_str())
-                    .expect("Failed to read glob pattern")
-            }
+
+impl Formats {
+    /// File extension used by data files of this format.
+    pub fn extension(&self) -> &'static str {
+        match *self {
+            Formats::Avro => "avro",
+            Formats::Parquet => "parquet",
+            Formats::Csv => "csv",
+            Formats::Jsonl => "jsonl",
+            Formats::Arrow => "arrow",
+            Formats::Sql => "sql",
+            Formats::LineProtocol => "lp",
         }
     }
-    for entry in paths {
-        match entry {
-            Ok(path) => {
-                println!("{:?}", path);
-                file_list.push(path)
-            }
-            Err(e) => println!("{:?}", e),
+}
+
+/// Lock state shared by the workers, failing if a worker panicked while holding it.
+pub fn lock<'a, T>(mutex: &'a Mutex<T>, what: &str) -> Result<MutexGuard<'a, T>> {
+    mutex
+        .lock()
+        .map_err(|_| Error::Worker(format!("a worker panicked while updating the {}", what)))
+}
+
+/// Take back state shared by the workers once they are done, failing if one panicked while
+/// holding it.
+pub fn into_inner<T>(mutex: Mutex<T>, what: &str) -> Result<T> {
+    mutex
+        .into_inner()
+        .map_err(|_| Error::Worker(format!("a worker panicked while updating the {}", what)))
+}
+
+/// Result of a worker thread, turning its panic into an error.
+pub fn join<T>(handle: ScopedJoinHandle<'_, Result<T>>) -> Result<T> {
+    handle
+        .join()
+        .map_err(|payload| Error::Worker(panic_message(payload)))?
+}
+
+fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    match payload.downcast::<String>() {
+        Ok(msg) => *msg,
+        Err(payload) => match payload.downcast::<&str>() {
+            Ok(msg) => msg.to_string(),
+            Err(_) => "a worker panicked".to_string(),
+        },
+    }
+}
+
+/// Path of the data file holding `table` in the dump directory.
+pub fn data_file_path(dir_path: &str, table: &str, format: &Formats) -> PathBuf {
+    Path::new(dir_path).join(format!("{}.{}", table, format.extension()))
+}
+
+/// Path of the data file holding the rows of `table` newer than `since`, written by an
+/// incremental dump.
+pub fn incremental_data_file_path(
+    dir_path: &str,
+    table: &str,
+    since: i64,
+    format: &Formats,
+) -> PathBuf {
+    Path::new(dir_path).join(format!("{}.{}.{}", table, since, format.extension()))
+}
+
+/// Every data file of `format` in the dump directory.
+pub fn get_all_data_files(dir_path: &str, format: &Formats) -> Result<Vec<PathBuf>> {
+    get_all_files(dir_path, format.extension())
+}
+
+/// Every file with the given extension in the dump directory, sorted by path.
+pub fn get_all_files(dir_path: &str, extension: &str) -> Result<Vec<PathBuf>> {
+    let dir = Path::new(dir_path);
+    let mut file_list = vec![];
+    for entry in fs::read_dir(dir).map_err(|err| Error::io(dir, err))? {
+        let path = entry.map_err(|err| Error::io(dir, err))?.path();
+        if path.extension().is_some_and(|ext| ext == extension) && path.is_file() {
+            file_list.push(path);
         }
     }
+    file_list.sort();
+    Ok(file_list)
 }
@@ -1,21 +1,131 @@
 use libtaos::*;
+use serde::Deserialize;
+use std::collections::HashMap;
 use std::env::var;
+use std::fs;
+use std::path::Path;
+
+use crate::error::{self, Result};
+
 fn var_or_default(env: &str, default: &str) -> String {
     var(env).unwrap_or(default.to_string())
 }
 
-pub fn taos_connect() -> Result<Taos, Error> {
-    TaosCfgBuilder::default()
-        .ip(&var_or_default("TEST_TAOS_IP", "127.0.0.1"))
-        .user(&var_or_default("TEST_TAOS_USER", "root"))
-        .pass(&var_or_default("TEST_TAOS_PASS", "taosdata"))
-        .db(&var_or_default("TEST_TAOS_DB", "log"))
-        .port(
-            var_or_default("TEST_TAOS_PORT", "6030")
+/// Where and as whom to connect to TDengine.
+#[derive(Debug, Clone)]
+pub struct ConnectOptions {
+    pub host: String,
+    pub port: u16,
+    pub user: String,
+    pub password: String,
+}
+
+impl ConnectOptions {
+    /// Options taken from the `TEST_TAOS_*` environment variables, or the TDengine defaults.
+    pub fn from_env() -> Result<Self> {
+        let port = var_or_default("TEST_TAOS_PORT", "6030");
+        Ok(ConnectOptions {
+            host: var_or_default("TEST_TAOS_IP", "127.0.0.1"),
+            port: port
                 .parse::<u16>()
-                .unwrap(),
-        )
+                .map_err(|_| error::Error::Config(format!("invalid port {}", port)))?,
+            user: var_or_default("TEST_TAOS_USER", "root"),
+            password: var_or_default("TEST_TAOS_PASS", "taosdata"),
+        })
+    }
+
+    /// Override the options set in `profile`.
+    pub fn merge(&mut self, profile: &Profile) {
+        if let Some(host) = &profile.host {
+            self.host = host.clone();
+        }
+        if let Some(port) = profile.port {
+            self.port = port;
+        }
+        if let Some(user) = &profile.user {
+            self.user = user.clone();
+        }
+        if let Some(password) = &profile.password {
+            self.password = password.clone();
+        }
+    }
+}
+
+/// Connection options of a config file, each of them optional.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Profile {
+    pub host: Option<String>,
+    pub port: Option<u16>,
+    pub user: Option<String>,
+    pub password: Option<String>,
+}
+
+/// A TOML config file: connection options at the top level, applied to every profile, and
+/// named profiles in `[profiles.<name>]` tables.
+///
+/// ```toml
+/// user = "root"
+///
+/// [profiles.production]
+/// host = "td1.example.com"
+/// password = "secret"
+/// ```
+#[derive(Debug, Default, Deserialize)]
+pub struct Config {
+    #[serde(flatten)]
+    pub default: Profile,
+    #[serde(default)]
+    pub profiles: HashMap<String, Profile>,
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path).map_err(|err| error::Error::io(path, err))?;
+        toml::from_str(&content).map_err(|err| {
+            error::Error::Config(format!("invalid config file {}: {}", path.display(), err))
+        })
+    }
+
+    /// Connection options of `profile`, or of the top level when no profile is given, on top of
+    /// the environment defaults.
+    pub fn connect_options(&self, profile: Option<&str>) -> Result<ConnectOptions> {
+        let mut options = ConnectOptions::from_env()?;
+        options.merge(&self.default);
+        if let Some(name) = profile {
+            match self.profiles.get(name) {
+                Some(profile) => options.merge(profile),
+                None => {
+                    return Err(error::Error::Config(format!(
+                        "profile {} not found in config file",
+                        name
+                    )))
+                }
+            }
+        }
+        Ok(options)
+    }
+}
+
+/// Ask for the password on the terminal without echoing it.
+pub fn prompt_password(user: &str) -> Result<String> {
+    rpassword::prompt_password_stderr(&format!("Enter password for {}: ", user))
+        .map_err(|err| error::Error::Config(format!("cannot read password: {}", err)))
+}
+
+/// Connect without a current database: callers `use` the database they work on, and libtaos
+/// would pick `log` when none is given.
+pub fn taos_connect(options: &ConnectOptions) -> Result<Taos> {
+    TaosCfgBuilder::default()
+        .ip(options.host.as_str())
+        .user(options.user.as_str())
+        .pass(options.password.as_str())
+        .db("")
+        .port(options.port)
         .build()
-        .expect("TaosCfg builder error")
+        .map_err(|err| error::Error::Config(err.to_string()))?
         .connect()
+        .map_err(|source| error::Error::Connection {
+            addr: format!("{}:{}", options.host, options.port),
+            source,
+        })
 }